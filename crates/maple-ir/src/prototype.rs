@@ -6,6 +6,7 @@ use crate::values::Value;
 
 pub struct PrototypeMethod<'a,> {
     closure: &'a mut Closure<'a>,
+    #[allow(dead_code)] // TODO: read once receivers are lowered
     reciever_values: Spread,
 }
 
@@ -23,6 +24,7 @@ impl<'a> PrototypeMethod<'a> {
 }
 
 pub struct Prototype<'a, T : Type, V : Value<T>> {
+    #[allow(dead_code)] // TODO: read once receivers are lowered
     pub(crate) receiver: V,
     pub(crate) methods: HashMap<String, &'a mut PrototypeMethod<'a>>,
     pub(crate) associated_type: T,
@@ -1,5 +1,3 @@
-use crate::{variable::VariableDeclaration, types::Type, values::Value};
-
 pub struct Spread {
     // TODO
 }
\ No newline at end of file
@@ -4,7 +4,7 @@ pub trait Value<T : Type> : TypeInfo<T> {}
 
 pub enum PrimitiveValue {
     StringValue(String),
-    IntegerValue(usize),
+    IntegerValue { value: u128, negative: bool }, // magnitude and sign, so any literal fits
     FloatValue(f64),
     BooleanValue(bool),
     Nil, // monotype null
@@ -16,7 +16,7 @@ impl TypeInfo<PrimitiveType> for PrimitiveValue {
     fn get_type(self) -> PrimitiveType {
         match self {
             PrimitiveValue::StringValue(_) => PrimitiveType::String,
-            PrimitiveValue::IntegerValue(_) => PrimitiveType::Integer,
+            PrimitiveValue::IntegerValue { .. } => PrimitiveType::Integer,
             PrimitiveValue::FloatValue(_) => PrimitiveType::Float,
             PrimitiveValue::BooleanValue(_) => PrimitiveType::Boolean,
             PrimitiveValue::Nil => PrimitiveType::Nil,
@@ -0,0 +1,56 @@
+//! Benchmarks for registering spans with a `FileMap`. Each group is run over
+//! files of increasing size, so the time per element should stay flat if
+//! span creation is linear in the size of the file.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use maple_lexer::{tokenize, CodeMap};
+
+const PROTOTYPE: &str = include_str!("../../../examples/maple-code/prototypes/proto_2023-12-22.maple");
+
+/// Sizes (in copies of the prototype example) to benchmark. The largest is
+/// roughly 50k tokens.
+const COPIES: &[usize] = &[4, 16, 100];
+
+fn register_tokens(c: &mut Criterion) {
+    let mut group = c.benchmark_group("register_tokens");
+
+    for &copies in COPIES {
+        let src = PROTOTYPE.repeat(copies);
+        let tokens = tokenize(&src).unwrap();
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("proto.maple", src);
+
+        group.throughput(Throughput::Elements(tokens.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(tokens.len()), &tokens, |b, tokens| {
+            b.iter_batched(|| tokens.clone(), |tokens| fm.register_tokens(tokens), BatchSize::LargeInput)
+        });
+    }
+
+    group.finish();
+}
+
+fn insert_and_lookup_spans(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_and_lookup_spans");
+
+    for &copies in COPIES {
+        let src = PROTOTYPE.repeat(copies);
+        let len = src.len();
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("proto.maple", src);
+
+        group.throughput(Throughput::Elements(len as u64));
+        group.bench_function(BenchmarkId::from_parameter(len), |b| {
+            b.iter(|| {
+                for start in 0..len {
+                    let span = fm.insert_span(start, start);
+                    assert!(fm.range_of(span).is_some());
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, register_tokens, insert_and_lookup_spans);
+criterion_main!(benches);
@@ -2,35 +2,97 @@
 //! contents.
 
 use std::collections::HashMap;
+use std::fs;
 use std::ops::Range;
-use std::rc::Rc;
-use std::cmp;
-use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::cmp;
+use std::thread;
 use serde::{Deserialize, Serialize};
-use crate::lex::{Token, TokenKind};
+use crate::errors::*;
+use crate::lex::{self, LosslessToken, RawTrivia, Token, TokenKind, Tokens, Trivia};
 
 
 /// A unique identifier pointing to a substring in some file.
 ///
+/// Every file in a `CodeMap` is given its own range of positions, so a span
+/// is just the half-open interval `[start, end)` in that shared address space
+/// and can be created or resolved without any bookkeeping.
+///
 /// To get back the original string this points to you'll need to look it up
 /// in a `CodeMap` or `FileMap`.
 #[derive(Copy, Clone, Debug, PartialEq, Hash, Eq, Serialize, Deserialize)]
-pub struct Span(usize);
+pub struct Span {
+    start: usize,
+    end: usize,
+}
 
 impl Span {
-    /// Returns the special "dummy" span, which matches anything. This should
-    /// only be used internally to make testing easier.
+    /// Returns the special "dummy" span, `0..0`, which isn't part of any file
+    /// in a `CodeMap`. It's an ordinary span which is only equal to itself,
+    /// so it should only be used as a placeholder to make testing easier.
     pub(crate) fn dummy() -> Span {
-        Span(0)
+        Span { start: 0, end: 0 }
     }
+
+    /// Create a span from raw positions. Only the lexer needs this, for spans
+    /// relative to the start of the text it's given.
+    pub(crate) fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    pub(crate) fn start(self) -> usize {
+        self.start
+    }
+
+    pub(crate) fn end(self) -> usize {
+        self.end
+    }
+
+    /// Move this span along by `offset` positions.
+    pub(crate) fn offset(self, offset: usize) -> Span {
+        Span::new(self.start + offset, self.end + offset)
+    }
+}
+
+/// A position in a file. Lines are 1-based, as are columns, although what a
+/// column counts depends on the `ColumnEncoding` used to calculate it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where a `Span` starts and ends, as lines and columns.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Location {
+    pub start: LineColumn,
+    /// The position just after the last character in the `Span`.
+    pub end: LineColumn,
+}
+
+/// The unit a column is measured in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ColumnEncoding {
+    /// UTF-8 bytes, i.e. the byte offset from the start of the line.
+    Utf8,
+    /// UTF-16 code units, as used by most editors and the Language Server
+    /// Protocol.
+    Utf16,
+    /// Unicode scalar values (`char`s), which is usually what a human would
+    /// count.
+    Char,
 }
 
 /// A mapping of `Span`s to the files in which they are located.
 #[derive(Debug)]
 pub struct CodeMap {
-    next_id: Rc<AtomicUsize>,
-    files: Vec<Rc<FileMap>>,
+    /// Where the next file's positions will start.
+    next_offset: usize,
+    files: Vec<Arc<FileMap>>,
+    /// Files which were loaded from disk, keyed by their canonical path.
+    loaded: HashMap<PathBuf, Arc<FileMap>>,
 }
 
 /// A mapping which keeps track of a file's contents and allows you to cheaply
@@ -39,49 +101,142 @@ pub struct CodeMap {
 pub struct FileMap {
     name: String,
     contents: String,
-    next_id: Rc<AtomicUsize>,
-    items: RefCell<HashMap<Span, Range<usize>>>
+    /// The byte offset of the start of each line.
+    lines: Vec<usize>,
+    /// The position of this file's first byte in its `CodeMap`.
+    start_pos: usize,
 }
 
 impl CodeMap {
     /// Create a new, empty `CodeMap`.
     pub fn new() -> CodeMap {
-        let next_id = Rc::new(AtomicUsize::new(1));
+        // position 0 is reserved for the dummy span
+        let next_offset = 1;
         let files = Vec::new();
-        CodeMap { next_id, files }
+        let loaded = HashMap::new();
+        CodeMap { next_offset, files, loaded }
     }
 
     /// Add a new file to the `CodeMap` and get back a reference to it.
-    pub fn insert_file<C, F>(&mut self, filename: F, contents: C) -> Rc<FileMap>
+    pub fn insert_file<C, F>(&mut self, filename: F, contents: C) -> Arc<FileMap>
         where F: Into<String>,
               C: Into<String>,
     {
+        let contents: String = contents.into();
+        let start_pos = self.next_offset;
+        // leave a gap so the end of this file isn't the start of the next one
+        self.next_offset += contents.len() + 1;
+
         let filemap = FileMap {
             name: filename.into(),
-            contents: contents.into(),
-            items: RefCell::new(HashMap::new()),
-            next_id: Rc::clone(&self.next_id),
+            lines: line_starts(&contents),
+            contents,
+            start_pos,
         };
-        let fm = Rc::new(filemap);
-        self.files.push(Rc::clone(&fm));
+        let fm = Arc::new(filemap);
+        self.files.push(Arc::clone(&fm));
 
         fm
     }
 
+    /// Read a file from disk and add it to the `CodeMap`.
+    ///
+    /// The file must be valid UTF-8, and a leading byte order mark is
+    /// stripped. Loading a file which has already been loaded (even through a
+    /// different path) gives back the original `FileMap`.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> ::std::result::Result<Arc<FileMap>, LoadError> {
+        let path = path.as_ref();
+        let io_error = |error| LoadError::Io { path: path.to_path_buf(), error };
+        let canonical = path.canonicalize().map_err(io_error)?;
+
+        if let Some(existing) = self.loaded.get(&canonical) {
+            return Ok(Arc::clone(existing));
+        }
+
+        let bytes = fs::read(&canonical).map_err(io_error)?;
+        let mut contents = String::from_utf8(bytes)
+            .map_err(|e| LoadError::Utf8 { path: path.to_path_buf(), error: e.utf8_error() })?;
+
+        if contents.starts_with(BYTE_ORDER_MARK) {
+            contents.drain(..BYTE_ORDER_MARK.len_utf8());
+        }
+
+        let fm = self.insert_file(path.display().to_string(), contents);
+        self.loaded.insert(canonical, Arc::clone(&fm));
+
+        Ok(fm)
+    }
+
     /// Get the substring that this `Span` corresponds to.
+    ///
+    /// # Panics
+    ///
+    /// If the span doesn't belong to any of the files in this `CodeMap`. Use
+    /// `try_lookup()` if that's a possibility.
     pub fn lookup(&self, span: Span) -> &str {
-        for filemap in &self.files {
-            if let Some(substr) = filemap.lookup(span) {
-                return substr;
-            }
+        match self.try_lookup(span) {
+            Some(substr) => substr,
+            None => panic!("Tried to lookup {:?} but it wasn't in any \
+                of the FileMaps... This is a bug!", span),
         }
+    }
 
-        panic!("Tried to lookup {:?} but it wasn't in any \
-            of the FileMaps... This is a bug!", span)
+    /// Get the substring that this `Span` corresponds to, if it belongs to one
+    /// of the files in this `CodeMap`.
+    pub fn try_lookup(&self, span: Span) -> Option<&str> {
+        self.file_of(span)?.lookup(span)
+    }
+
+    /// Find the file a `Span` belongs to.
+    ///
+    /// Files are stored in the order their positions were handed out, so this
+    /// is just a binary search.
+    pub fn file_of(&self, span: Span) -> Option<&Arc<FileMap>> {
+        let ix = self.files
+            .partition_point(|fm| fm.start_pos <= span.start)
+            .checked_sub(1)?;
+        let filemap = &self.files[ix];
+
+        filemap.range_of(span).map(|_| filemap)
+    }
+
+    /// Tokenize every file in the `CodeMap`, spreading the work across as
+    /// many threads as the machine has available. The results are in the same
+    /// order as `files()`.
+    pub fn tokenize_all(&self) -> Vec<Result<Vec<Token>>> {
+        let num_threads = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(self.files.len());
+        let next_file = AtomicUsize::new(0);
+
+        let mut results: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..num_threads)
+                .map(|_| scope.spawn(|| {
+                    let mut done = Vec::new();
+
+                    // keep grabbing files until there are none left, so one
+                    // big file doesn't hold up a whole batch of small ones
+                    loop {
+                        let ix = next_file.fetch_add(1, Ordering::Relaxed);
+                        match self.files.get(ix) {
+                            Some(fm) => done.push((ix, fm.tokenize())),
+                            None => return done,
+                        }
+                    }
+                }))
+                .collect();
+
+            workers.into_iter()
+                .flat_map(|worker| worker.join().expect("A lexer thread panicked"))
+                .collect()
+        });
+
+        results.sort_by_key(|&(ix, _)| ix);
+        results.into_iter().map(|(_, tokens)| tokens).collect()
     }
 
     /// The files that this `CodeMap` contains.
-    pub fn files(&self) -> &[Rc<FileMap>] {
+    pub fn files(&self) -> &[Arc<FileMap>] {
         self.files.as_slice()
     }
 }
@@ -92,6 +247,17 @@ impl Default for CodeMap {
     }
 }
 
+const BYTE_ORDER_MARK: char = '\u{feff}';
+
+/// Find the byte offset of the start of each line in `src`. Lines end with
+/// `\n` or `\r\n`, the same as newline trivia in the lexer, so a lone `\r`
+/// is just whitespace.
+fn line_starts(src: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(src.match_indices('\n').map(|(ix, _)| ix + 1));
+    starts
+}
+
 impl FileMap {
     /// Get the name of this `FileMap`.
     pub fn filename(&self) -> &str {
@@ -107,14 +273,11 @@ impl FileMap {
     ///
     /// # Panics
     ///
-    /// If the `FileMap`'s `items` hashmap contains a span, but that span
-    /// **doesn't** point to a valid substring this will panic. If you ever
-    /// get into a situation like this then things are almost certainly FUBAR.
+    /// If the span lies within this `FileMap` but **doesn't** point to a valid
+    /// substring (i.e. it splits a codepoint) this will panic. If you ever get
+    /// into a situation like this then things are almost certainly FUBAR.
     pub fn lookup(&self, span: Span) -> Option<&str> {
-        let range = match self.range_of(span) {
-            Some(r) => r,
-            None => return None,
-        };
+        let range = self.range_of(span)?;
 
         match self.contents.get(range.clone()) {
             Some(substr) => Some(substr),
@@ -125,7 +288,66 @@ impl FileMap {
 
     /// Get the range corresponding to this span.
     pub fn range_of(&self, span: Span) -> Option<Range<usize>> {
-        self.items.borrow().get(&span).cloned()
+        let end_pos = self.start_pos + self.contents.len();
+
+        if self.start_pos <= span.start && span.end <= end_pos {
+            Some(span.start - self.start_pos..span.end - self.start_pos)
+        } else {
+            None
+        }
+    }
+
+    /// The number of lines in this file.
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Get the contents of a line (1-based), without its line ending.
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let start = *self.lines.get(line.checked_sub(1)?)?;
+        let end = self.lines.get(line).copied().unwrap_or(self.contents.len());
+        let text = &self.contents[start..end];
+        let text = text.strip_suffix('\n').map_or(text, |line| line.strip_suffix('\r').unwrap_or(line));
+
+        Some(text)
+    }
+
+    /// Figure out which line and column a byte offset lies on.
+    ///
+    /// # Panics
+    ///
+    /// If the offset lies outside the file or doesn't lie on a char boundary.
+    pub fn offset_to_line_col(&self, offset: usize, encoding: ColumnEncoding) -> LineColumn {
+        assert!(self.contents.is_char_boundary(offset),
+                "{} isn't a valid offset into {}", offset, self.name);
+
+        let line_index = self.lines.partition_point(|&start| start <= offset) - 1;
+        let before = &self.contents[self.lines[line_index]..offset];
+
+        let column = match encoding {
+            ColumnEncoding::Utf8 => before.len(),
+            ColumnEncoding::Utf16 => before.encode_utf16().count(),
+            ColumnEncoding::Char => before.chars().count(),
+        };
+
+        LineColumn { line: line_index + 1, column: column + 1 }
+    }
+
+    /// The line and column (in UTF-8 bytes) that a span starts at.
+    pub fn line_col(&self, span: Span) -> Option<LineColumn> {
+        let range = self.range_of(span)?;
+        Some(self.offset_to_line_col(range.start, ColumnEncoding::Utf8))
+    }
+
+    /// Where a span starts and ends, with columns measured in the given
+    /// encoding.
+    pub fn span_to_location(&self, span: Span, encoding: ColumnEncoding) -> Option<Location> {
+        let range = self.range_of(span)?;
+
+        Some(Location {
+            start: self.offset_to_line_col(range.start, encoding),
+            end: self.offset_to_line_col(range.end, encoding),
+        })
     }
 }
 
@@ -145,33 +367,15 @@ impl FileMap {
                       "Start doesn't lie on a char boundary");
         debug_assert!(self.contents.is_char_boundary(end),
                       "End doesn't lie on a char boundary");
-        debug_assert!(start < self.contents.len(),
+        debug_assert!(start <= self.contents.len(),
                       "Start lies outside the content string");
         debug_assert!(end <= self.contents.len(),
                       "End lies outside the content string");
 
-        let range = start..end;
-
-        if let Some(existing) = self.reverse_lookup(&range) {
-            return existing;
+        Span {
+            start: self.start_pos + start,
+            end: self.start_pos + end,
         }
-
-        let span_id = self.next_id.fetch_add(1, Ordering::Relaxed);
-        let span = Span(span_id);
-
-        self.items.borrow_mut().insert(span, range);
-        span
-    }
-
-    /// We don't want to go and add duplicate spans unnecessarily so we
-    /// iterate through all existing ranges to see if this one already
-    /// exists.
-    fn reverse_lookup(&self, needle: &Range<usize>) -> Option<Span> {
-        self.items.borrow()
-            .iter()
-            .find(|&(_, range)| range == needle)
-            .map(|(span, _)| span)
-            .cloned()
     }
 
     /// Merge two spans to get the span which includes both.
@@ -202,11 +406,65 @@ impl FileMap {
 
         registered
     }
+
+    /// Register the output of `tokenize_lossless()`, keeping each token's
+    /// trivia. Note that all the caveats from `insert_span()` also apply
+    /// here.
+    pub fn register_lossless_tokens(&self, tokens: Vec<LosslessToken>) -> Vec<Token> {
+        let mut registered = Vec::new();
+
+        for tok in tokens {
+            let mut token = Token::new(self.insert_span(tok.start, tok.end), tok.kind);
+            token.leading_trivia = self.register_trivia(tok.leading_trivia);
+            token.trailing_trivia = self.register_trivia(tok.trailing_trivia);
+            registered.push(token);
+        }
+
+        registered
+    }
+
+    /// Register an error from one of the lexing functions (e.g.
+    /// `tokenize()`), so its span points into this file.
+    pub fn register_error(&self, error: Error) -> Error {
+        error.offset(self.start_pos)
+    }
+
+    /// Tokenize this file's contents.
+    pub fn tokenize(&self) -> Result<Vec<Token>> {
+        let tokens = lex::tokenize(&self.contents)
+            .map_err(|e| self.register_error(e))?;
+        Ok(self.register_tokens(tokens))
+    }
+
+    /// Lazily tokenize this file's contents.
+    pub fn tokens(&self) -> Tokens<'_> {
+        Tokens::new(self)
+    }
+
+    /// Tokenize this file's contents, carrying on past any errors. See
+    /// `tokenize_recovering()` for more.
+    pub fn tokenize_recovering(&self) -> (Vec<Token>, Vec<Error>) {
+        let (tokens, diagnostics) = lex::tokenize_recovering(&self.contents);
+
+        let diagnostics = diagnostics.into_iter()
+            .map(|error| self.register_error(error))
+            .collect();
+
+        (self.register_tokens(tokens), diagnostics)
+    }
+
+    fn register_trivia(&self, trivia: Vec<RawTrivia>) -> Vec<Trivia> {
+        trivia.into_iter()
+            .map(|(kind, start, end)| Trivia { kind, span: self.insert_span(start, end) })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::TempDir;
+    use crate::diagnostics::Renderer;
 
     #[test]
     fn insert_a_file_into_a_codemap() {
@@ -277,6 +535,110 @@ mod tests {
         assert_eq!(span_1, span_2);
     }
 
+    #[test]
+    fn spans_only_belong_to_their_own_file() {
+        let mut map = CodeMap::new();
+        let first = map.insert_file("first.rs", "Hello");
+        let second = map.insert_file("second.rs", "World!");
+
+        let end_of_first = first.insert_span(5, 5);
+        let start_of_second = second.insert_span(0, 0);
+
+        assert_ne!(end_of_first, start_of_second);
+        assert_eq!(first.range_of(end_of_first), Some(5..5));
+        assert_eq!(second.range_of(end_of_first), None);
+        assert_eq!(first.range_of(start_of_second), None);
+        assert_eq!(second.range_of(start_of_second), Some(0..0));
+        assert_eq!(map.lookup(second.insert_span(0, 5)), "World");
+    }
+
+    #[test]
+    fn codemaps_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<CodeMap>();
+        assert_send_sync::<FileMap>();
+        assert_send_sync::<Span>();
+        assert_send_sync::<Token>();
+    }
+
+    #[test]
+    fn tokenize_all_files_in_parallel() {
+        let mut map = CodeMap::new();
+        let files: Vec<_> = (0..20)
+            .map(|i| map.insert_file(format!("{}.maple", i), "val x = 1\n".repeat(i)))
+            .collect();
+        map.insert_file("broken.maple", "val x = `");
+
+        let results = map.tokenize_all();
+
+        assert_eq!(results.len(), 21);
+        for (fm, tokens) in files.iter().zip(&results) {
+            assert_eq!(tokens.as_ref().unwrap(), &fm.tokenize().unwrap());
+        }
+        assert!(results[20].is_err());
+    }
+
+    #[test]
+    fn tokenize_all_on_an_empty_codemap() {
+        let map = CodeMap::new();
+        assert!(map.tokenize_all().is_empty());
+    }
+
+    #[test]
+    fn find_the_file_a_span_belongs_to() {
+        let mut map = CodeMap::new();
+        let files: Vec<_> = (0..10)
+            .map(|i| map.insert_file(format!("{}.maple", i), format!("file number {}", i)))
+            .collect();
+
+        for fm in &files {
+            let span = fm.insert_span(5, 11);
+            assert_eq!(map.file_of(span).unwrap().filename(), fm.filename());
+            assert_eq!(map.try_lookup(span), Some("number"));
+
+            let whole_file = fm.insert_span(0, fm.contents().len());
+            assert_eq!(map.file_of(whole_file).unwrap().filename(), fm.filename());
+        }
+    }
+
+    #[test]
+    fn spans_outside_the_codemap_arent_found() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "Hello World!");
+
+        let mut other_map = CodeMap::new();
+        other_map.insert_file("bar.maple", "Hello World!");
+        let other = other_map.insert_file("baz.maple", "Goodbye World!");
+        let foreign = other.insert_span(8, 13);
+
+        assert!(map.file_of(Span::dummy()).is_none());
+        assert!(map.try_lookup(Span::dummy()).is_none());
+        assert!(map.file_of(foreign).is_none());
+        assert!(map.try_lookup(fm.insert_span(0, 5)).is_some());
+    }
+
+    #[test]
+    fn spans_crossing_files_arent_found() {
+        let mut map = CodeMap::new();
+        let first = map.insert_file("first.maple", "Hello");
+        let second = map.insert_file("second.maple", "World!");
+
+        let crossing = Span {
+            start: first.insert_span(0, 1).start,
+            end: second.insert_span(0, 1).end,
+        };
+
+        assert!(map.file_of(crossing).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn looking_up_an_unknown_span_panics() {
+        let map = CodeMap::new();
+        map.lookup(Span::dummy());
+    }
+
     #[test]
     fn join_multiple_spans() {
         let mut map = CodeMap::new();
@@ -292,4 +654,195 @@ mod tests {
         assert_eq!(equivalent_range.start, 0);
         assert_eq!(equivalent_range.end, 8);
     }
+
+    #[test]
+    fn resolve_lines_and_columns() {
+        let mut map = CodeMap::new();
+        let src = "foo\nbar baz\r\n\nqux";
+        let fm = map.insert_file("foo.rs", src);
+
+        assert_eq!(fm.line_count(), 4);
+        assert_eq!(fm.line_col(fm.insert_span(0, 3)), Some(LineColumn { line: 1, column: 1 }));
+        assert_eq!(fm.line_col(fm.insert_span(8, 11)), Some(LineColumn { line: 2, column: 5 }));
+        assert_eq!(fm.line_col(fm.insert_span(13, 13)), Some(LineColumn { line: 3, column: 1 }));
+        assert_eq!(fm.line_col(fm.insert_span(14, 17)), Some(LineColumn { line: 4, column: 1 }));
+
+        let location = fm.span_to_location(fm.insert_span(4, 17), ColumnEncoding::Utf8).unwrap();
+        assert_eq!(location.start, LineColumn { line: 2, column: 1 });
+        assert_eq!(location.end, LineColumn { line: 4, column: 4 });
+    }
+
+    #[test]
+    fn get_the_text_of_a_line() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.rs", "foo\nbar baz\r\n\nqux");
+
+        assert_eq!(fm.line(0), None);
+        assert_eq!(fm.line(1), Some("foo"));
+        assert_eq!(fm.line(2), Some("bar baz"));
+        assert_eq!(fm.line(3), Some(""));
+        assert_eq!(fm.line(4), Some("qux"));
+        assert_eq!(fm.line(5), None);
+    }
+
+    #[test]
+    fn columns_in_different_encodings() {
+        let mut map = CodeMap::new();
+        // 'é' is 2 bytes in UTF-8, '𝄞' is 4 bytes and 2 UTF-16 code units
+        let src = "val é𝄞 = x";
+        let fm = map.insert_file("foo.rs", src);
+        let x = src.find('x').unwrap();
+
+        assert_eq!(fm.offset_to_line_col(x, ColumnEncoding::Utf8).column, 14);
+        assert_eq!(fm.offset_to_line_col(x, ColumnEncoding::Utf16).column, 11);
+        assert_eq!(fm.offset_to_line_col(x, ColumnEncoding::Char).column, 10);
+    }
+
+    #[test]
+    fn recovering_diagnostics_point_at_the_bad_input() {
+        let mut map = CodeMap::new();
+        let src = "foo ` bar";
+        let fm = map.insert_file("foo.rs", src);
+
+        let (tokens, diagnostics) = fm.tokenize_recovering();
+
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[1].kind, TokenKind::Error);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(fm.lookup(diagnostics[0].span()), Some("`"));
+    }
+
+    #[test]
+    fn lexer_errors_can_be_rendered() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "val x = 0x");
+
+        let err = fm.tokenize().unwrap_err();
+        let rendered = Renderer::new(&map).render(&err.into());
+
+        let should_be = "\
+error[E0007]: Expected a digit
+ --> foo.maple:1:11
+  |
+1 | val x = 0x
+  |           ^ expected a digit here
+  |
+";
+        assert_eq!(rendered, should_be);
+    }
+
+    #[test]
+    fn lossless_tokens_reconstruct_the_file() {
+        let mut map = CodeMap::new();
+        let src = "foo = /* bar */ 1 // baz\n";
+        let fm = map.insert_file("foo.rs", src);
+
+        let tokens = crate::lex::tokenize_lossless(src).unwrap();
+        let tokens = fm.register_lossless_tokens(tokens);
+
+        let mut reconstructed = String::new();
+        for token in &tokens {
+            for trivia in &token.leading_trivia {
+                reconstructed.push_str(fm.lookup(trivia.span).unwrap());
+            }
+            reconstructed.push_str(fm.lookup(token.span).unwrap());
+            for trivia in &token.trailing_trivia {
+                reconstructed.push_str(fm.lookup(trivia.span).unwrap());
+            }
+        }
+
+        assert_eq!(reconstructed, src);
+        assert_eq!(tokens.last().unwrap().kind, TokenKind::EOF);
+    }
+
+    /// Write `contents` to a file called `name` in a new temporary directory,
+    /// which is deleted when the returned `TempDir` is dropped.
+    fn temp_file(name: &str, contents: &[u8]) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn load_a_file_from_disk() {
+        let (_dir, path) = temp_file("load.maple", b"val x = 1\n");
+        let mut map = CodeMap::new();
+
+        let fm = map.load_file(&path).unwrap();
+
+        assert_eq!(fm.filename(), path.display().to_string());
+        assert_eq!(fm.contents(), "val x = 1\n");
+        assert_eq!(fm.tokenize().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn byte_order_marks_are_stripped() {
+        let (_dir, path) = temp_file("bom.maple", b"\xef\xbb\xbfval x = 1");
+        let mut map = CodeMap::new();
+
+        let fm = map.load_file(&path).unwrap();
+
+        assert_eq!(fm.contents(), "val x = 1");
+        assert_eq!(fm.offset_to_line_col(0, ColumnEncoding::Char), LineColumn { line: 1, column: 1 });
+    }
+
+    #[test]
+    fn invalid_utf8_reports_the_bad_byte() {
+        let (_dir, path) = temp_file("invalid.maple", b"\xef\xbb\xbfval \xff = 1");
+        let mut map = CodeMap::new();
+
+        match map.load_file(&path).unwrap_err() {
+            LoadError::Utf8 { path: got, error } => {
+                assert_eq!(got, path);
+                assert_eq!(error.valid_up_to(), 7);
+            },
+            other => panic!("Unexpected error: {}", other),
+        }
+        assert!(map.files().is_empty());
+    }
+
+    #[test]
+    fn missing_files_are_an_io_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.maple");
+        let mut map = CodeMap::new();
+
+        let err = map.load_file(&path).unwrap_err();
+
+        assert!(matches!(err, LoadError::Io { .. }), "{}", err);
+        assert_eq!(err.path(), path);
+    }
+
+    #[test]
+    fn loading_the_same_file_twice_reuses_it() {
+        let (_dir, path) = temp_file("twice.maple", b"val x = 1");
+        let roundabout = path.parent().unwrap().join(".").join("twice.maple");
+        let mut map = CodeMap::new();
+
+        let first = map.load_file(&path).unwrap();
+        let second = map.load_file(&roundabout).unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(map.files().len(), 1);
+    }
+
+    #[test]
+    fn line_tables_use_the_same_line_endings_as_the_lexer() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "a\r\nb\rc\nd\r");
+
+        assert_eq!(fm.line_count(), 3);
+        let lines: Vec<_> = (1..=3).map(|n| fm.line(n).unwrap()).collect();
+        assert_eq!(lines, vec!["a", "b\rc", "d\r"]);
+        assert_eq!(fm.offset_to_line_col(5, ColumnEncoding::Utf8), LineColumn { line: 2, column: 3 });
+        assert_eq!(fm.offset_to_line_col(1, ColumnEncoding::Utf8), LineColumn { line: 1, column: 2 });
+
+        // a lone `\r` isn't a newline to the lexer either
+        let newlines = crate::lex::tokenize_lossless(fm.contents()).unwrap().iter()
+            .flat_map(|tok| tok.leading_trivia.iter().chain(&tok.trailing_trivia))
+            .filter(|&&(kind, _, _)| kind == crate::lex::TriviaKind::Newline)
+            .count();
+        assert_eq!(newlines, 2);
+    }
 }
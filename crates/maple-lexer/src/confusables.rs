@@ -0,0 +1,110 @@
+//! Warnings about identifiers which could be mistaken for something else
+//! (see [UTS #39](https://www.unicode.org/reports/tr39/)).
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use unicode_security::{skeleton, MixedScript};
+use crate::codemap::Span;
+use crate::diagnostics::Diagnostic;
+use crate::lex::{Token, TokenKind};
+
+/// Check the identifiers in a stream of tokens, warning about any which mix
+/// several scripts (e.g. Latin and Cyrillic) or which are different but look
+/// the same as an identifier used earlier.
+///
+/// Each distinct identifier is only reported once, where it's first used.
+pub fn check_identifiers(tokens: &[Token]) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    let mut checked = HashSet::new();
+    // the first identifier seen with each skeleton
+    let mut lookalikes: HashMap<String, (&str, Span)> = HashMap::new();
+
+    for token in tokens {
+        let name = match &token.kind {
+            TokenKind::Identifier(name) => name.as_str(),
+            _ => continue,
+        };
+
+        if !checked.insert(name) {
+            continue;
+        }
+
+        if !name.is_single_script() {
+            let message = format!("Identifier `{}` mixes characters from several scripts", name);
+            warnings.push(Diagnostic::warning(message, token.span)
+                .with_code("W0001")
+                .with_note("this makes it easy to confuse with other identifiers"));
+        }
+
+        match lookalikes.entry(skeleton(name).collect()) {
+            Entry::Occupied(entry) => {
+                let (original, original_span) = *entry.get();
+
+                // plain ASCII names like `l1` and `ll` are left alone, they
+                // can't be fixed by being more careful with unicode
+                if !(name.is_ascii() && original.is_ascii()) {
+                    let message = format!("Identifier `{}` looks like `{}`", name, original);
+                    warnings.push(Diagnostic::warning(message, token.span)
+                        .with_code("W0002")
+                        .with_label(original_span, "the other identifier is used here"));
+                }
+            },
+            Entry::Vacant(entry) => {
+                entry.insert((name, token.span));
+            },
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::CodeMap;
+    use crate::diagnostics::Severity;
+
+    fn warnings_for(src: &str) -> Vec<Diagnostic> {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", src);
+
+        check_identifiers(&fm.tokenize().unwrap())
+    }
+
+    #[test]
+    fn normal_identifiers_are_fine() {
+        let src = include_str!("../../../examples/maple-code/prototypes/proto_2023-12-22.maple");
+
+        assert!(warnings_for(src).is_empty());
+        assert!(warnings_for("val größe = λ + 日本語").is_empty());
+    }
+
+    #[test]
+    fn mixed_script_identifiers_are_reported() {
+        // the "о" is a Cyrillic small letter o
+        let warnings = warnings_for("val fоo = 1\nval x = fоo");
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert_eq!(warnings[0].code.as_deref(), Some("W0001"));
+    }
+
+    #[test]
+    fn lookalike_identifiers_are_reported() {
+        // "рара" is spelt entirely with Cyrillic letters
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "val papa = 1\nval рара = 2");
+
+        let warnings = check_identifiers(&fm.tokenize().unwrap());
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code.as_deref(), Some("W0002"));
+        assert_eq!(map.lookup(warnings[0].primary.span), "рара");
+        assert_eq!(map.lookup(warnings[0].secondary[0].span), "papa");
+    }
+
+    #[test]
+    fn ascii_lookalikes_are_ignored() {
+        assert!(warnings_for("val l1 = ll + rn + m").is_empty());
+    }
+}
@@ -0,0 +1,667 @@
+//! Rendering errors and warnings with the source code they point at, in the
+//! same style as `rustc`.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthChar;
+use crate::codemap::{CodeMap, ColumnEncoding, FileMap, LineColumn, Span};
+
+/// How serious a `Diagnostic` is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Help => "help",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => GREEN,
+            Severity::Help => CYAN,
+        }
+    }
+}
+
+/// A span in the source code with a message explaining its relevance.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new<S: Into<String>>(span: Span, message: S) -> Label {
+        Label { span, message: message.into() }
+    }
+}
+
+/// A proposed fix, replacing the code in `span` with `replacement`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+    pub message: String,
+}
+
+/// Something to tell the user about their code, usually an error.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A short, unique identifier for this kind of diagnostic (e.g. `E0001`).
+    pub code: Option<String>,
+    pub message: String,
+    /// Where the problem is. The label's message may be empty.
+    pub primary: Label,
+    /// Other places which help explain the problem.
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    /// Create a new diagnostic which points at `span`.
+    pub fn new<S: Into<String>>(severity: Severity, message: S, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code: None,
+            message: message.into(),
+            primary: Label::new(span, ""),
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            help: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    /// Create a new error which points at `span`.
+    pub fn error<S: Into<String>>(message: S, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message, span)
+    }
+
+    /// Create a new warning which points at `span`.
+    pub fn warning<S: Into<String>>(message: S, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message, span)
+    }
+
+    pub fn with_code<S: Into<String>>(mut self, code: S) -> Diagnostic {
+        self.code = Some(code.into());
+        self
+    }
+
+    /// Set the message shown next to the primary span.
+    pub fn with_primary_message<S: Into<String>>(mut self, message: S) -> Diagnostic {
+        self.primary.message = message.into();
+        self
+    }
+
+    pub fn with_label<S: Into<String>>(mut self, span: Span, message: S) -> Diagnostic {
+        self.secondary.push(Label::new(span, message));
+        self
+    }
+
+    pub fn with_note<S: Into<String>>(mut self, note: S) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help<S: Into<String>>(mut self, help: S) -> Diagnostic {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Suggest replacing the code in `span` with `replacement`.
+    pub fn with_suggestion<R, M>(mut self, span: Span, replacement: R, message: M) -> Diagnostic
+        where R: Into<String>,
+              M: Into<String>,
+    {
+        self.suggestions.push(Suggestion {
+            span,
+            replacement: replacement.into(),
+            message: message.into(),
+        });
+        self
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const CYAN: &str = "\x1b[1;36m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// How many lines in the middle of a multi-line span are shown before the
+/// rest are replaced with `...`.
+const MAX_MULTILINE_CONTEXT: usize = 3;
+
+/// Width of a tab when displaying source code.
+const TAB_WIDTH: usize = 4;
+
+/// Turns `Diagnostic`s into human readable text, optionally with ANSI colors.
+#[derive(Debug, Copy, Clone)]
+pub struct Renderer<'a> {
+    codemap: &'a CodeMap,
+    color: bool,
+}
+
+/// A label which has been resolved to a file and display positions.
+struct Annotation<'a> {
+    file: &'a Arc<FileMap>,
+    primary: bool,
+    message: &'a str,
+    start_line: usize,
+    end_line: usize,
+    /// Display columns (0-based), with `end_col` being exclusive.
+    start_col: usize,
+    end_col: usize,
+    /// Where the header's `file:line:col` should point.
+    location: LineColumn,
+}
+
+impl<'a> Annotation<'a> {
+    fn is_multiline(&self) -> bool {
+        self.start_line != self.end_line
+    }
+}
+
+impl<'a> Renderer<'a> {
+    /// Create a new `Renderer` which doesn't use color.
+    pub fn new(codemap: &'a CodeMap) -> Renderer<'a> {
+        Renderer { codemap, color: false }
+    }
+
+    /// Turn ANSI colors on or off.
+    pub fn with_color(mut self, color: bool) -> Renderer<'a> {
+        self.color = color;
+        self
+    }
+
+    /// Render a diagnostic to a string, ending in a newline.
+    ///
+    /// Labels whose spans don't belong to the `CodeMap` are skipped.
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        let severity = diagnostic.severity;
+
+        out.push_str(&self.paint(severity.color(), severity.name()));
+        if let Some(code) = &diagnostic.code {
+            out.push_str(&self.paint(severity.color(), &format!("[{}]", code)));
+        }
+        out.push_str(&self.paint(BOLD, &format!(": {}", diagnostic.message)));
+        out.push('\n');
+
+        let annotations: Vec<_> = std::iter::once((&diagnostic.primary, true))
+            .chain(diagnostic.secondary.iter().map(|label| (label, false)))
+            .filter_map(|(label, primary)| self.annotate(label, primary))
+            .collect();
+
+        let gutter_width = annotations.iter()
+            .map(|a| a.end_line.to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        // the primary label's file comes first, then the others in the order
+        // they were mentioned
+        let mut files: Vec<&Arc<FileMap>> = Vec::new();
+        for annotation in &annotations {
+            if !files.iter().any(|f| Arc::ptr_eq(f, annotation.file)) {
+                files.push(annotation.file);
+            }
+        }
+
+        for (i, file) in files.iter().enumerate() {
+            let in_file: Vec<_> = annotations.iter()
+                .filter(|a| Arc::ptr_eq(a.file, file))
+                .collect();
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            let location = in_file[0].location;
+
+            let _ = writeln!(out, "{:w$}{} {}:{}:{}", "", self.paint(BLUE, arrow),
+                             file.filename(), location.line, location.column,
+                             w = gutter_width);
+            self.render_snippet(&mut out, file, &in_file, gutter_width, severity);
+        }
+
+        let suggestions: Vec<_> = diagnostic.suggestions.iter()
+            .map(|s| format!("{}: `{}`", s.message, s.replacement))
+            .collect();
+        let notes = diagnostic.notes.iter().map(|note| ("note", note));
+        let help = diagnostic.help.iter().chain(&suggestions).map(|help| ("help", help));
+        for (kind, text) in notes.chain(help) {
+            let _ = writeln!(out, "{:w$} {} {}: {}", "", self.paint(BLUE, "="),
+                             self.paint(BOLD, kind), text, w = gutter_width);
+        }
+
+        out
+    }
+
+    fn annotate<'b>(&self, label: &'b Label, primary: bool) -> Option<Annotation<'b>>
+        where 'a: 'b
+    {
+        let file = self.codemap.file_of(label.span)?;
+        let range = file.range_of(label.span)?;
+
+        let start = file.offset_to_line_col(range.start, ColumnEncoding::Utf8);
+        let mut end = file.offset_to_line_col(range.end, ColumnEncoding::Utf8);
+
+        // a span which includes the newline at the end of a line shouldn't
+        // spill over onto the next one
+        if end.column == 1 && end.line > start.line {
+            let previous = file.line(end.line - 1).unwrap_or("");
+            end = LineColumn { line: end.line - 1, column: previous.len() + 1 };
+        }
+
+        let start_col = display_width(line_prefix(file, start));
+        let mut end_col = display_width(line_prefix(file, end));
+        if start.line == end.line && end_col <= start_col {
+            // always underline at least one character
+            end_col = start_col + 1;
+        }
+
+        Some(Annotation {
+            file,
+            primary,
+            message: &label.message,
+            start_line: start.line,
+            end_line: end.line,
+            start_col,
+            end_col,
+            location: file.offset_to_line_col(range.start, ColumnEncoding::Char),
+        })
+    }
+
+    fn render_snippet(&self, out: &mut String, file: &FileMap, annotations: &[&Annotation],
+                      gutter_width: usize, severity: Severity) {
+        let multiline: Vec<_> = annotations.iter().filter(|a| a.is_multiline()).collect();
+        let depth = multiline.len();
+
+        let mut lines = BTreeSet::new();
+        for annotation in annotations {
+            lines.insert(annotation.start_line);
+            lines.insert(annotation.end_line);
+
+            if annotation.end_line - annotation.start_line <= MAX_MULTILINE_CONTEXT + 1 {
+                lines.extend(annotation.start_line..annotation.end_line);
+            }
+        }
+
+        let blank = format!("{:w$} {}", "", self.paint(BLUE, "|"), w = gutter_width);
+        let _ = writeln!(out, "{}", blank);
+
+        let mut previous_line = None;
+        for &line in &lines {
+            if previous_line.is_some_and(|prev| line > prev + 1) {
+                let _ = writeln!(out, "{}", self.paint(BLUE, "..."));
+            }
+            previous_line = Some(line);
+
+            // the source line itself
+            let gutters = self.gutters(&multiline, |a| a.start_line < line && line <= a.end_line);
+            let text = file.line(line).unwrap_or("").replace('\t', &" ".repeat(TAB_WIDTH));
+            let number = self.paint(BLUE, &format!("{:>w$} |", line, w = gutter_width));
+            let _ = writeln!(out, "{} {}{}", number, gutters, text);
+            trim_end(out);
+
+            let continuing = |a: &Annotation| a.start_line <= line && line < a.end_line;
+
+            // underlines for labels which start and end on this line
+            let mut singles: Vec<_> = annotations.iter()
+                .filter(|a| !a.is_multiline() && a.start_line == line)
+                .collect();
+            singles.sort_by_key(|a| a.start_col);
+
+            if !singles.is_empty() {
+                let mut row = String::new();
+                for annotation in &singles {
+                    pad_to(&mut row, annotation.start_col);
+                    let marker = if annotation.primary { "^" } else { "-" };
+                    let underline = marker.repeat(annotation.end_col - annotation.start_col);
+                    row.push_str(&self.paint(self.label_color(annotation, severity), &underline));
+                }
+
+                let last = singles[singles.len() - 1];
+                if !last.message.is_empty() {
+                    row.push(' ');
+                    row.push_str(&self.paint(self.label_color(last, severity), last.message));
+                }
+
+                let gutters = self.gutters(&multiline, continuing);
+                let _ = writeln!(out, "{} {}{}", blank, gutters, row);
+
+                // any other messages hang off their underline
+                for annotation in singles[..singles.len() - 1].iter().rev() {
+                    if annotation.message.is_empty() {
+                        continue;
+                    }
+
+                    let mut row = String::new();
+                    pad_to(&mut row, annotation.start_col);
+                    row.push_str(&self.paint(self.label_color(annotation, severity), annotation.message));
+                    let _ = writeln!(out, "{} {}{}", blank, gutters, row);
+                }
+            }
+
+            // the beginning and end of multi-line labels
+            for (d, annotation) in multiline.iter().enumerate() {
+                let color = self.label_color(annotation, severity);
+                let marker = if annotation.primary { "^" } else { "-" };
+
+                let (column, message) = if annotation.start_line == line {
+                    (annotation.start_col, "")
+                } else if annotation.end_line == line {
+                    (annotation.end_col.saturating_sub(1), annotation.message)
+                } else {
+                    continue;
+                };
+
+                let mut row = String::new();
+                for (other_depth, other) in multiline.iter().enumerate() {
+                    if other_depth == d {
+                        let lead = if annotation.start_line == line { " " } else { "|" };
+                        row.push_str(&self.paint(color, lead));
+                        break;
+                    } else if continuing(other) {
+                        row.push_str(&self.paint(self.label_color(other, severity), "|"));
+                    } else {
+                        row.push(' ');
+                    }
+                }
+
+                let underscores = depth - d + column;
+                let mut connector = "_".repeat(underscores);
+                connector.push_str(marker);
+                row.push_str(&self.paint(color, &connector));
+
+                if !message.is_empty() {
+                    row.push(' ');
+                    row.push_str(&self.paint(color, message));
+                }
+
+                let _ = writeln!(out, "{} {}", blank, row);
+            }
+        }
+
+        let _ = writeln!(out, "{}", blank);
+    }
+
+    /// The column of `|`s for multi-line labels to the left of the source.
+    fn gutters<F>(&self, multiline: &[&&Annotation], active: F) -> String
+        where F: Fn(&Annotation) -> bool
+    {
+        if multiline.is_empty() {
+            return String::new();
+        }
+
+        let mut gutters = String::new();
+        for annotation in multiline {
+            if active(annotation) {
+                gutters.push_str(&self.paint(BLUE, "|"));
+            } else {
+                gutters.push(' ');
+            }
+        }
+        gutters.push(' ');
+
+        gutters
+    }
+
+    fn label_color(&self, annotation: &Annotation, severity: Severity) -> &'static str {
+        if annotation.primary {
+            severity.color()
+        } else {
+            BLUE
+        }
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// The part of a line before a position.
+fn line_prefix(file: &FileMap, position: LineColumn) -> &str {
+    let line = file.line(position.line).unwrap_or("");
+    let end = std::cmp::min(position.column - 1, line.len());
+    &line[..end]
+}
+
+/// How many columns some text takes up when displayed. Wide characters
+/// (e.g. CJK or emoji) take up two columns, and control characters none.
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|ch| if ch == '\t' { TAB_WIDTH } else { ch.width().unwrap_or(0) })
+        .sum()
+}
+
+/// Pad a row of underlines with spaces until it reaches `column`. Only
+/// visible characters are counted, so ANSI escape codes are skipped.
+fn pad_to(row: &mut String, column: usize) {
+    let mut visible = 0;
+    let mut in_escape = false;
+
+    for ch in row.chars() {
+        match ch {
+            '\x1b' => in_escape = true,
+            'm' if in_escape => in_escape = false,
+            _ if !in_escape => visible += 1,
+            _ => {},
+        }
+    }
+
+    for _ in visible..column {
+        row.push(' ');
+    }
+}
+
+/// Remove any trailing whitespace from the last line written to `out`.
+fn trim_end(out: &mut String) {
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(map: &CodeMap, diagnostic: &Diagnostic) -> String {
+        Renderer::new(map).render(diagnostic)
+    }
+
+    #[test]
+    fn render_a_single_line_error() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "val x = `oops`\nval y = 2\n");
+
+        let diagnostic = Diagnostic::error("Unknown character", fm.insert_span(8, 9))
+            .with_code("E0001")
+            .with_primary_message("this isn't valid here")
+            .with_note("backticks aren't used in Maple")
+            .with_help("did you mean to write a string?")
+            .with_suggestion(fm.insert_span(8, 9), "\"", "use double quotes");
+        let should_be = "\
+error[E0001]: Unknown character
+ --> foo.maple:1:9
+  |
+1 | val x = `oops`
+  |         ^ this isn't valid here
+  |
+  = note: backticks aren't used in Maple
+  = help: did you mean to write a string?
+  = help: use double quotes: `\"`
+";
+
+        assert_eq!(render(&map, &diagnostic), should_be);
+    }
+
+    #[test]
+    fn render_secondary_labels() {
+        let mut map = CodeMap::new();
+        let src = "val x = 1\nval x = 2\n";
+        let fm = map.insert_file("foo.maple", src);
+
+        let diagnostic = Diagnostic::error("Duplicate definition of `x`", fm.insert_span(14, 15))
+            .with_primary_message("redefined here")
+            .with_label(fm.insert_span(4, 5), "first defined here");
+        let should_be = "\
+error: Duplicate definition of `x`
+ --> foo.maple:2:5
+  |
+1 | val x = 1
+  |     - first defined here
+2 | val x = 2
+  |     ^ redefined here
+  |
+";
+
+        assert_eq!(render(&map, &diagnostic), should_be);
+    }
+
+    #[test]
+    fn render_several_labels_on_one_line() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "a + b");
+
+        let diagnostic = Diagnostic::error("Can't add these", fm.insert_span(2, 3))
+            .with_primary_message("no `plus` operator")
+            .with_label(fm.insert_span(0, 1), "this is a String")
+            .with_label(fm.insert_span(4, 5), "this is an Int");
+        let should_be = "\
+error: Can't add these
+ --> foo.maple:1:3
+  |
+1 | a + b
+  | - ^ - this is an Int
+  |   no `plus` operator
+  | this is a String
+  |
+";
+
+        assert_eq!(render(&map, &diagnostic), should_be);
+    }
+
+    #[test]
+    fn render_a_multiline_span() {
+        let mut map = CodeMap::new();
+        let src = "object Person {\n    val name: Name\n}\n";
+        let fm = map.insert_file("foo.maple", src);
+
+        let diagnostic = Diagnostic::warning("Empty-ish object", fm.insert_span(14, 36))
+            .with_primary_message("this object");
+        let should_be = "\
+warning: Empty-ish object
+ --> foo.maple:1:15
+  |
+1 |   object Person {
+  |  _______________^
+2 | |     val name: Name
+3 | | }
+  | |_^ this object
+  |
+";
+
+        assert_eq!(render(&map, &diagnostic), should_be);
+    }
+
+    #[test]
+    fn long_multiline_spans_are_elided() {
+        let mut map = CodeMap::new();
+        let src = "{\n1\n2\n3\n4\n5\n6\n}";
+        let fm = map.insert_file("foo.maple", src);
+
+        let diagnostic = Diagnostic::error("Block", fm.insert_span(0, src.len()));
+        let rendered = render(&map, &diagnostic);
+
+        assert!(rendered.contains("1 |   {\n"), "{}", rendered);
+        assert!(rendered.contains("...\n"), "{}", rendered);
+        assert!(rendered.contains("8 | | }\n"), "{}", rendered);
+        assert!(!rendered.contains("4 | | 3"), "{}", rendered);
+    }
+
+    #[test]
+    fn wide_characters_take_up_two_columns() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "val 名前 = `");
+
+        let diagnostic = Diagnostic::error("Unknown character", fm.insert_span(13, 14));
+        let should_be = "\
+error: Unknown character
+ --> foo.maple:1:10
+  |
+1 | val 名前 = `
+  |            ^
+  |
+";
+
+        assert_eq!(render(&map, &diagnostic), should_be);
+    }
+
+    #[test]
+    fn render_labels_in_other_files() {
+        let mut map = CodeMap::new();
+        let first = map.insert_file("first.maple", "val x: Paycheck = 1");
+        let second = map.insert_file("second.maple", "object Paycheck {}");
+
+        let diagnostic = Diagnostic::error("Mismatched types", first.insert_span(18, 19))
+            .with_label(second.insert_span(7, 15), "Paycheck is defined here");
+        let should_be = "\
+error: Mismatched types
+ --> first.maple:1:19
+  |
+1 | val x: Paycheck = 1
+  |                   ^
+  |
+ ::: second.maple:1:8
+  |
+1 | object Paycheck {}
+  |        -------- Paycheck is defined here
+  |
+";
+
+        assert_eq!(render(&map, &diagnostic), should_be);
+    }
+
+    #[test]
+    fn render_with_color() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "val x = `");
+
+        let diagnostic = Diagnostic::error("Unknown character", fm.insert_span(8, 9));
+        let rendered = Renderer::new(&map).with_color(true).render(&diagnostic);
+
+        assert!(rendered.starts_with("\x1b[1;31merror\x1b[0m"), "{:?}", rendered);
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"), "{:?}", rendered);
+    }
+
+    #[test]
+    fn unknown_spans_are_skipped() {
+        let map = CodeMap::new();
+        let diagnostic = Diagnostic::error("Something broke", Span::dummy())
+            .with_note("but we don't know where");
+
+        assert_eq!(render(&map, &diagnostic),
+                   "error: Something broke\n = note: but we don't know where\n");
+    }
+}
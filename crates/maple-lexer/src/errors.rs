@@ -1,33 +1,292 @@
-//! Types and traits used for internal errors.
+//! The errors which can happen while lexing.
 
-use error_chain::error_chain;
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::Utf8Error;
+use crate::codemap::Span;
+use crate::diagnostics::Diagnostic;
 
-error_chain!{
-    errors {
-        /// Got to the end of the input stream but was expecting more.
-        UnexpectedEOF {
-            display("Unexpected EOF")
-            description("Unexpected EOF")
+/// Something went wrong while lexing, and where it happened.
+///
+/// Errors from the raw lexing functions (e.g. `tokenize()`) have spans
+/// relative to the start of the string being lexed, as if it were the first
+/// thing in a `CodeMap` at position 0. The `FileMap` methods convert them to
+/// proper spans which can be looked up in the `CodeMap`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Got to the end of the input stream but was expecting more.
+    UnexpectedEOF { span: Span },
+    /// Reached a character which can't start a token.
+    UnknownCharacter { ch: char, span: Span },
+    /// A string literal was still open when the input ran out (or, for
+    /// single-line strings, when the line ended). The span runs from the
+    /// opening quote to where the string was cut off.
+    UnterminatedString { span: Span },
+    /// A block comment was still open when the input ran out.
+    UnterminatedComment { span: Span },
+    /// A backslash in a string literal wasn't followed by a valid escape.
+    InvalidEscape { sequence: String, span: Span },
+    /// A `\u{...}` escape whose hex digits weren't followed by a `}`. The
+    /// span runs from the backslash to the last digit.
+    UnterminatedEscape { span: Span },
+    /// A digit which isn't valid for the number's base.
+    InvalidDigit { ch: char, radix: u32, span: Span },
+    /// A `_` digit separator which isn't between two digits, like `1_` or
+    /// `0x_ff`.
+    MisplacedSeparator { span: Span },
+    /// A number prefix (e.g. `0x`) or exponent wasn't followed by any
+    /// digits. The span is empty and points at where a digit was expected.
+    MissingDigits { span: Span },
+    /// A number was followed by a type suffix we don't know about, or one
+    /// which doesn't make sense for that kind of number.
+    InvalidSuffix { suffix: String, span: Span },
+    /// An integer literal was too big to store, even before checking it
+    /// against the type it's used as.
+    IntegerOverflow { span: Span },
+    /// A character or byte literal with nothing in it (`''`).
+    EmptyCharLiteral { span: Span },
+    /// A character or byte literal with more than one character in it.
+    OverlongCharLiteral { span: Span },
+    /// A character or byte literal which wasn't closed before the end of the
+    /// line.
+    UnterminatedCharLiteral { span: Span },
+    /// A byte literal or byte string containing a character which doesn't
+    /// fit in a single byte.
+    NonAsciiByte { ch: char, span: Span },
+}
+
+/// A specialised `Result` for lexing.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl Error {
+    /// The part of the source code this error applies to.
+    pub fn span(&self) -> Span {
+        match *self {
+            Error::UnexpectedEOF { span }
+            | Error::UnknownCharacter { span, .. }
+            | Error::UnterminatedString { span }
+            | Error::UnterminatedComment { span }
+            | Error::InvalidEscape { span, .. }
+            | Error::UnterminatedEscape { span }
+            | Error::InvalidDigit { span, .. }
+            | Error::MisplacedSeparator { span }
+            | Error::MissingDigits { span }
+            | Error::InvalidSuffix { span, .. }
+            | Error::IntegerOverflow { span }
+            | Error::EmptyCharLiteral { span }
+            | Error::OverlongCharLiteral { span }
+            | Error::UnterminatedCharLiteral { span }
+            | Error::NonAsciiByte { span, .. } => span,
         }
+    }
 
-        /// Reached an unknown character while lexing.
-        UnknownCharacter(ch: char) {
-            display("Unknown Character, {:?}", ch)
-            description("Unknown Character")
+    /// The unique code used to identify this kind of error in diagnostics.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Error::UnexpectedEOF { .. } => "E0001",
+            Error::UnknownCharacter { .. } => "E0002",
+            Error::UnterminatedString { .. } => "E0003",
+            Error::UnterminatedComment { .. } => "E0004",
+            Error::InvalidEscape { .. } => "E0005",
+            Error::InvalidDigit { .. } => "E0006",
+            Error::MissingDigits { .. } => "E0007",
+            Error::InvalidSuffix { .. } => "E0008",
+            Error::IntegerOverflow { .. } => "E0009",
+            Error::EmptyCharLiteral { .. } => "E0010",
+            Error::OverlongCharLiteral { .. } => "E0011",
+            Error::UnterminatedCharLiteral { .. } => "E0012",
+            Error::NonAsciiByte { .. } => "E0013",
+            Error::UnterminatedEscape { .. } => "E0014",
+            Error::MisplacedSeparator { .. } => "E0015",
         }
+    }
+
+    /// Turn this error into a `Diagnostic` which can be shown to the user.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.to_string(), self.span())
+            .with_code(self.code());
 
-        /// A message which corresponds to some location in the source code.
-        MessageWithLocation(loc: usize, msg: &'static str) {
-            display("{} at {}", msg, loc)
-            description("Custom Error")
+        match *self {
+            Error::UnterminatedString { .. } => diagnostic
+                .with_primary_message("this string is never closed"),
+            Error::UnterminatedComment { .. } => diagnostic
+                .with_primary_message("this comment is never closed")
+                .with_note("block comments can be nested, so every `/*` needs a matching `*/`"),
+            Error::InvalidEscape { span, .. } => {
+                let backslash = Span::new(span.start(), span.start() + 1);
+                diagnostic
+                    .with_help(r#"valid escapes are \n, \t, \r, \0, \\, \", \', \$ and \u{...}, plus \x.. in byte literals"#)
+                    .with_suggestion(backslash, r"\\", "if you meant a literal backslash, escape it")
+            },
+            Error::UnterminatedEscape { .. } => diagnostic
+                .with_primary_message("expected a `}` after this")
+                .with_help(r"unicode escapes are written as 1 to 6 hex digits in braces, e.g. \u{1F341}"),
+            Error::MissingDigits { .. } => diagnostic
+                .with_primary_message("expected a digit here"),
+            Error::MisplacedSeparator { .. } => diagnostic
+                .with_help("`_` can only be used between digits, e.g. `1_000`"),
+            Error::InvalidSuffix { .. } => diagnostic
+                .with_help("valid suffixes are u8 to u128, i8 to i128, f32 and f64"),
+            Error::IntegerOverflow { .. } => diagnostic
+                .with_note("integer literals can't be bigger than 128 bits"),
+            Error::OverlongCharLiteral { .. } => diagnostic
+                .with_primary_message("this should contain exactly one character")
+                .with_help("if you meant to write a string, use double quotes"),
+            Error::UnterminatedCharLiteral { .. } => diagnostic
+                .with_primary_message("this literal is never closed"),
+            Error::NonAsciiByte { .. } => diagnostic
+                .with_help(r"use a \x escape (e.g. \xFF) for bytes which aren't ASCII"),
+            _ => diagnostic,
         }
     }
 
-    foreign_links {
-        Io(::std::io::Error) #[doc = "Wrapper around a `std::io::Error`"];
-        Utf8(::std::str::Utf8Error) #[doc = "An error parsing data as UTF-8"];
-        FloatParsing(::std::num::ParseFloatError) #[doc = "A float parsing error"];
-        IntParsing(::std::num::ParseIntError) #[doc = "An integer parsing error"];
+    /// Move this error's span along by `offset` positions.
+    pub(crate) fn offset(mut self, offset: usize) -> Error {
+        let new_span = self.span().offset(offset);
+        *self.span_mut() = new_span;
+        self
+    }
+
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Error::UnexpectedEOF { span }
+            | Error::UnknownCharacter { span, .. }
+            | Error::UnterminatedString { span }
+            | Error::UnterminatedComment { span }
+            | Error::InvalidEscape { span, .. }
+            | Error::UnterminatedEscape { span }
+            | Error::InvalidDigit { span, .. }
+            | Error::MisplacedSeparator { span }
+            | Error::MissingDigits { span }
+            | Error::InvalidSuffix { span, .. }
+            | Error::IntegerOverflow { span }
+            | Error::EmptyCharLiteral { span }
+            | Error::OverlongCharLiteral { span }
+            | Error::UnterminatedCharLiteral { span }
+            | Error::NonAsciiByte { span, .. } => span,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnexpectedEOF { .. } => write!(f, "Unexpected EOF"),
+            Error::UnknownCharacter { ch, .. } => write!(f, "Unknown character, {:?}", ch),
+            Error::UnterminatedString { .. } => write!(f, "Unterminated string literal"),
+            Error::UnterminatedComment { .. } => write!(f, "Unterminated block comment"),
+            Error::InvalidEscape { sequence, .. } => write!(f, "Invalid escape sequence, {:?}", sequence),
+            Error::UnterminatedEscape { .. } => write!(f, "Unterminated unicode escape"),
+            Error::InvalidDigit { ch, radix, .. } => {
+                write!(f, "Invalid digit {:?} for a base {} number", ch, radix)
+            },
+            Error::MissingDigits { .. } => write!(f, "Expected a digit"),
+            Error::MisplacedSeparator { .. } => write!(f, "Digit separator isn't between two digits"),
+            Error::InvalidSuffix { suffix, .. } => write!(f, "Invalid number suffix, {:?}", suffix),
+            Error::IntegerOverflow { .. } => write!(f, "Integer literal doesn't fit in 128 bits"),
+            Error::EmptyCharLiteral { .. } => write!(f, "Empty character literal"),
+            Error::OverlongCharLiteral { .. } => {
+                write!(f, "Character literal contains more than one character")
+            },
+            Error::UnterminatedCharLiteral { .. } => write!(f, "Unterminated character literal"),
+            Error::NonAsciiByte { ch, .. } => write!(f, "Non-ASCII character in byte literal, {:?}", ch),
+        }
+    }
+}
+
+impl StdError for Error {}
+
+impl From<Error> for Diagnostic {
+    fn from(other: Error) -> Diagnostic {
+        other.to_diagnostic()
+    }
+}
+
+impl<'a> From<&'a Error> for Diagnostic {
+    fn from(other: &'a Error) -> Diagnostic {
+        other.to_diagnostic()
+    }
+}
+
+/// Something went wrong while loading a file into a `CodeMap`.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file couldn't be read.
+    Io { path: PathBuf, error: io::Error },
+    /// The file isn't valid UTF-8.
+    Utf8 { path: PathBuf, error: Utf8Error },
+}
+
+impl LoadError {
+    /// The file which couldn't be loaded.
+    pub fn path(&self) -> &Path {
+        match self {
+            LoadError::Io { path, .. } | LoadError::Utf8 { path, .. } => path,
+        }
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io { path, error } => {
+                write!(f, "Couldn't read {}: {}", path.display(), error)
+            },
+            LoadError::Utf8 { path, error } => {
+                write!(f, "{} isn't valid UTF-8, found an invalid byte at offset {}",
+                       path.display(), error.valid_up_to())
+            },
+        }
+    }
+}
+
+impl StdError for LoadError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            LoadError::Io { error, .. } => Some(error),
+            LoadError::Utf8 { error, .. } => Some(error),
+        }
+    }
+}
+
+/// Something went wrong while loading and lexing a file with `lex_file()`.
+#[derive(Debug)]
+pub enum FileError {
+    /// The file couldn't be loaded.
+    Load(LoadError),
+    /// The file was loaded but its contents couldn't be lexed. The error's
+    /// span can be looked up in the `CodeMap` the file was loaded into.
+    Lex(Error),
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::Load(error) => Display::fmt(error, f),
+            FileError::Lex(error) => Display::fmt(error, f),
+        }
+    }
+}
+
+impl StdError for FileError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            FileError::Load(error) => Some(error),
+            FileError::Lex(error) => Some(error),
+        }
+    }
+}
+
+impl From<LoadError> for FileError {
+    fn from(other: LoadError) -> FileError {
+        FileError::Load(other)
+    }
+}
 
+impl From<Error> for FileError {
+    fn from(other: Error) -> FileError {
+        FileError::Lex(other)
     }
 }
@@ -0,0 +1,216 @@
+//! Re-lexing a file after it has been edited, without starting from
+//! scratch.
+//!
+//! Only the region around the edit is lexed again. Lexing stops as soon as
+//! it produces a token which was also in the old token list (once shifted
+//! to account for the edit), because everything after that point is
+//! guaranteed to be the same.
+
+use std::ops::Range;
+use crate::errors::*;
+use crate::lex::{TokenKind, Tokenizer};
+
+/// How many characters past the end of a token the lexer may have looked
+/// at to decide where the token ends (e.g. to see whether `1.` is followed
+/// by a digit, or `1e+` by an exponent).
+const MAX_LOOKAHEAD: usize = 4;
+
+/// A change to some source code, replacing the bytes in `range` with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn new<S: Into<String>>(range: Range<usize>, text: S) -> Edit {
+        Edit { range, text: text.into() }
+    }
+
+    /// Apply this edit to the original text.
+    pub fn apply(&self, src: &str) -> String {
+        let mut edited = src.to_string();
+        edited.replace_range(self.range.clone(), &self.text);
+        edited
+    }
+
+    /// How many bytes longer (or shorter) this makes the text.
+    fn delta(&self) -> isize {
+        self.text.len() as isize - self.range.len() as isize
+    }
+}
+
+/// Which tokens `relex()` replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relexed {
+    /// The indices of the tokens which were removed from the old list.
+    pub removed: Range<usize>,
+    /// The indices of the tokens which replaced them in the new list.
+    pub inserted: Range<usize>,
+}
+
+/// Update the tokens from `tokenize()` after an edit, where `src` is the
+/// source code *after* the edit was applied.
+///
+/// Tokens before the edit are kept, and tokens after it are kept but moved
+/// to their new position. Only the tokens in between are lexed again. If
+/// lexing fails `tokens` is left untouched.
+pub fn relex(src: &str, tokens: &mut Vec<(TokenKind, usize, usize)>, edit: &Edit) -> Result<Relexed> {
+    let edit_end = edit.range.start + edit.text.len();
+    let shift = |offset: usize| (offset as isize + edit.delta()) as usize;
+    let depths = string_depths(tokens);
+
+    // Back up to a token which ended a safe distance before the edit,
+    // outside of any strings. The text before the edit hasn't changed, so
+    // we can measure that distance in the new source code.
+    let mut first = tokens.partition_point(|&(_, _, end)| end < edit.range.start);
+    while first > 0 {
+        let (_, _, end) = tokens[first - 1];
+        let distance = src[end..edit.range.start].chars().count();
+
+        if depths[first] == 0 && distance >= MAX_LOOKAHEAD {
+            break;
+        }
+        first -= 1;
+    }
+    let (restart, previous) = match first.checked_sub(1).map(|ix| &tokens[ix]) {
+        Some((kind, _, end)) => (*end, Some(kind)),
+        None => (0, None),
+    };
+
+    // Lex until we get back in sync with the old tokens after the edit
+    let mut tokenizer = Tokenizer::starting_at(src, restart, previous);
+    let mut new_tokens = Vec::new();
+    let mut old = tokens.partition_point(|&(_, start, _)| start < edit.range.end);
+
+    loop {
+        let at_top_level = tokenizer.at_top_level();
+        let (kind, start, end) = match tokenizer.next_token()? {
+            Some(tok) => tok,
+            None => {
+                old = tokens.len();
+                break;
+            },
+        };
+
+        if at_top_level && start >= edit_end {
+            while old < tokens.len() && shift(tokens[old].1) < start {
+                old += 1;
+            }
+
+            let in_sync = tokens.get(old).is_some_and(|(old_kind, old_start, old_end)| {
+                depths[old] == 0 && shift(*old_start) == start && shift(*old_end) == end && *old_kind == kind
+            });
+            if in_sync {
+                break;
+            }
+        }
+
+        new_tokens.push((kind, start, end));
+    }
+
+    for tok in &mut tokens[old..] {
+        tok.1 = shift(tok.1);
+        tok.2 = shift(tok.2);
+    }
+
+    let inserted = first..first + new_tokens.len();
+    tokens.splice(first..old, new_tokens);
+
+    Ok(Relexed { removed: first..old, inserted })
+}
+
+/// How many strings are open before each token, plus one extra entry for
+/// the end of the input.
+fn string_depths(tokens: &[(TokenKind, usize, usize)]) -> Vec<usize> {
+    let mut depths = Vec::with_capacity(tokens.len() + 1);
+    let mut depth = 0;
+    depths.push(depth);
+
+    for (kind, _, _) in tokens {
+        match kind {
+            TokenKind::StringStart => depth += 1,
+            TokenKind::StringEnd => depth -= 1,
+            _ => {},
+        }
+        depths.push(depth);
+    }
+
+    depths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::tokenize;
+
+    /// Make sure re-lexing after an edit gives the same result as lexing the
+    /// edited source from scratch, returning what was re-lexed.
+    fn check_relex(src: &str, edit: Edit) -> Relexed {
+        let edited = edit.apply(src);
+        let mut tokens = tokenize(src).unwrap();
+
+        let got = relex(&edited, &mut tokens, &edit).unwrap();
+
+        assert_eq!(tokens, tokenize(&edited).unwrap(), "{:?} => {:?}", src, edited);
+        got
+    }
+
+    #[test]
+    fn relexing_matches_tokenize() {
+        let inputs = vec![
+            ("foo = bar + baz", Edit::new(7..7, "ium")),
+            ("foo = bar + baz", Edit::new(6..9, "")),
+            ("foo = bar + baz", Edit::new(0..0, "val ")),
+            ("foo = bar + baz", Edit::new(15..15, " * 2")),
+            ("foo = bar + baz", Edit::new(0..15, "x")),
+            ("foo = 1.x", Edit::new(8..9, "5")),
+            ("foo = 1 +5", Edit::new(7..8, "e")),
+            ("a + b", Edit::new(2..3, "++")),
+            ("a b", Edit::new(1..2, "")),
+            ("foo // comment\nbar", Edit::new(6..6, "/")),
+            ("a \"b\" c \"d\" e", Edit::new(4..9, "")),
+            ("a \"b ${c + d} e\" f", Edit::new(9..10, "-")),
+            ("a \"b ${c + d} e\" f", Edit::new(4..4, "$x ")),
+            ("a\n\"\"\"b\nc\"\"\" d", Edit::new(7..7, "\"\"\" + \"\"\"")),
+            ("val s = 'a' + b'c'", Edit::new(9..10, "\\n")),
+            ("x = a.5", Edit::new(4..5, "-")),
+            ("x = -.5", Edit::new(4..5, "a")),
+        ];
+
+        for (src, edit) in inputs {
+            check_relex(src, edit);
+        }
+    }
+
+    #[test]
+    fn only_the_damaged_region_is_relexed() {
+        let src = include_str!("../../../examples/maple-code/prototypes/proto_2023-12-22.maple").repeat(20);
+        let offset = src.len() / 2;
+        let offset = src[offset..].find("name").unwrap() + offset;
+
+        let got = check_relex(&src, Edit::new(offset..offset + 4, "fullName"));
+
+        assert!(got.removed.len() <= 4, "{:?}", got);
+        assert!(got.inserted.len() <= 4, "{:?}", got);
+        assert_eq!(got.removed.len(), got.inserted.len());
+    }
+
+    #[test]
+    fn edits_inside_strings_resync_after_the_string() {
+        let got = check_relex("a \"b ${c} d\" e", Edit::new(3..4, "bb"));
+
+        // the old tokens can't be reused until the string is closed
+        assert_eq!(got.removed, 0..9);
+    }
+
+    #[test]
+    fn errors_leave_the_tokens_alone() {
+        let src = "foo = bar";
+        let edit = Edit::new(6..6, "`");
+        let mut tokens = tokenize(src).unwrap();
+
+        assert!(relex(&edit.apply(src), &mut tokens, &edit).is_err());
+        assert_eq!(tokens, tokenize(src).unwrap());
+    }
+}
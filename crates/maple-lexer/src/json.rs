@@ -0,0 +1,201 @@
+//! Machine-readable diagnostics, written as one JSON object per line in
+//! the same spirit as `rustc --error-format=json`.
+
+use std::io::{self, Write};
+use serde::{Deserialize, Serialize};
+use crate::codemap::{CodeMap, ColumnEncoding, Span};
+use crate::diagnostics::{Diagnostic, Label, Renderer, Severity};
+
+/// A `Diagnostic` with all its spans resolved, ready to be serialized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonDiagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+    pub message: String,
+    /// The primary span comes first, followed by any secondary ones.
+    pub spans: Vec<JsonSpan>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+    pub suggestions: Vec<JsonSuggestion>,
+    /// The diagnostic as it would be shown to a human, without colors.
+    pub rendered: String,
+}
+
+/// Where a span lies in its file.
+///
+/// Byte offsets are from the start of the file and are half-open. Lines and
+/// columns are 1-based, with columns counted in `char`s and the end pointing
+/// just past the last character.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonSpan {
+    pub file: String,
+    pub byte_start: usize,
+    pub byte_end: usize,
+    pub line_start: usize,
+    pub column_start: usize,
+    pub line_end: usize,
+    pub column_end: usize,
+    pub is_primary: bool,
+    pub label: Option<String>,
+}
+
+/// A suggested fix, replacing the code in `span` with `replacement`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonSuggestion {
+    pub message: String,
+    pub replacement: String,
+    pub span: JsonSpan,
+}
+
+/// Writes `Diagnostic`s to some output as JSON, one object per line.
+#[derive(Debug)]
+pub struct JsonEmitter<'a, W> {
+    codemap: &'a CodeMap,
+    writer: W,
+}
+
+impl<'a, W: Write> JsonEmitter<'a, W> {
+    pub fn new(codemap: &'a CodeMap, writer: W) -> JsonEmitter<'a, W> {
+        JsonEmitter { codemap, writer }
+    }
+
+    /// Write a single diagnostic, followed by a newline.
+    pub fn emit(&mut self, diagnostic: &Diagnostic) -> io::Result<()> {
+        let json = to_json(self.codemap, diagnostic);
+        serde_json::to_writer(&mut self.writer, &json)?;
+        self.writer.write_all(b"\n")
+    }
+
+    /// Get back the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Resolve a diagnostic's spans against the `CodeMap`. Any spans which don't
+/// belong to it are left out, as are suggestions which use them.
+pub fn to_json(codemap: &CodeMap, diagnostic: &Diagnostic) -> JsonDiagnostic {
+    let primary = std::iter::once((&diagnostic.primary, true));
+    let secondary = diagnostic.secondary.iter().map(|label| (label, false));
+
+    let spans = primary.chain(secondary)
+        .filter_map(|(label, is_primary)| {
+            let Label { span, message } = label;
+            let label = if message.is_empty() { None } else { Some(message.clone()) };
+            resolve(codemap, *span, is_primary, label)
+        })
+        .collect();
+
+    let suggestions = diagnostic.suggestions.iter()
+        .filter_map(|suggestion| {
+            Some(JsonSuggestion {
+                message: suggestion.message.clone(),
+                replacement: suggestion.replacement.clone(),
+                span: resolve(codemap, suggestion.span, false, None)?,
+            })
+        })
+        .collect();
+
+    JsonDiagnostic {
+        severity: diagnostic.severity,
+        code: diagnostic.code.clone(),
+        message: diagnostic.message.clone(),
+        spans,
+        notes: diagnostic.notes.clone(),
+        help: diagnostic.help.clone(),
+        suggestions,
+        rendered: Renderer::new(codemap).render(diagnostic),
+    }
+}
+
+fn resolve(codemap: &CodeMap, span: Span, is_primary: bool, label: Option<String>) -> Option<JsonSpan> {
+    let file = codemap.file_of(span)?;
+    let range = file.range_of(span)?;
+    let location = file.span_to_location(span, ColumnEncoding::Char)?;
+
+    Some(JsonSpan {
+        file: file.filename().to_string(),
+        byte_start: range.start,
+        byte_end: range.end,
+        line_start: location.start.line,
+        column_start: location.start.column,
+        line_end: location.end.line,
+        column_end: location.end.column,
+        is_primary,
+        label,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emit_one_diagnostic_per_line() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "val s = \"ab\\qc\"\nval t = 0x");
+
+        let diagnostics: Vec<Diagnostic> = vec![
+            fm.tokenize_recovering().1[0].to_diagnostic(),
+            Diagnostic::warning("Unused value", fm.insert_span(20, 21)),
+        ];
+
+        let mut emitter = JsonEmitter::new(&map, Vec::new());
+        for diagnostic in &diagnostics {
+            emitter.emit(diagnostic).unwrap();
+        }
+        let output = String::from_utf8(emitter.into_inner()).unwrap();
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        let first: JsonDiagnostic = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first, to_json(&map, &diagnostics[0]));
+        let second: JsonDiagnostic = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second.severity, Severity::Warning);
+    }
+
+    #[test]
+    fn spans_have_byte_and_line_column_ranges() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "val s = \"ab\\qc\"\n");
+
+        let (_, errors) = fm.tokenize_recovering();
+        let got = to_json(&map, &errors[0].to_diagnostic());
+
+        assert_eq!(got.severity, Severity::Error);
+        assert_eq!(got.code.as_deref(), Some("E0005"));
+        assert_eq!(got.message, errors[0].to_string());
+        assert_eq!(got.spans, vec![JsonSpan {
+            file: "foo.maple".to_string(),
+            byte_start: 11,
+            byte_end: 13,
+            line_start: 1,
+            column_start: 12,
+            line_end: 1,
+            column_end: 14,
+            is_primary: true,
+            label: None,
+        }]);
+        assert_eq!(got.suggestions.len(), 1);
+        assert_eq!(got.suggestions[0].replacement, "\\\\");
+        assert_eq!((got.suggestions[0].span.byte_start, got.suggestions[0].span.byte_end), (11, 12));
+        assert!(got.rendered.starts_with("error[E0005]"));
+    }
+
+    #[test]
+    fn json_uses_the_expected_field_names() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "a");
+
+        let diagnostic = Diagnostic::error("Oops", fm.insert_span(0, 1))
+            .with_label(fm.insert_span(0, 1), "here");
+        let got = serde_json::to_value(to_json(&map, &diagnostic)).unwrap();
+
+        assert_eq!(got["severity"], "error");
+        assert_eq!(got["code"], serde_json::Value::Null);
+        assert_eq!(got["spans"][0]["is_primary"], true);
+        assert_eq!(got["spans"][1]["label"], "here");
+        assert_eq!(got["spans"][1]["column_end"], 2);
+    }
+}
@@ -1,34 +1,81 @@
 //! Token definitions
 
 use std::str;
-use error_chain::bail;
-use crate::codemap::Span;
+use std::char;
+use std::collections::VecDeque;
+use crate::codemap::{FileMap, Span};
 use crate::errors::*;
 use serde::{Deserialize, Serialize};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use unicode_xid::UnicodeXID;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum TokenKind {
     // Values
-    Integer(usize),
-    Decimal(f64),
-    QuotedString(String),
+    // Integers are always stored unsigned and as wide as possible, checking
+    // that they fit their actual type is left to the type checker.
+    Integer { value: u128, suffix: Option<NumberSuffix> },
+    Decimal { value: f64, suffix: Option<NumberSuffix> },
+    QuotedString(String), // A string literal without any interpolation
+    Character(char), // A 'c' character literal
+    Byte(u8), // A b'c' byte literal
+    ByteString(Vec<u8>), // A b"..." byte string literal, which is never interpolated
+
+    // Interpolated strings are split into a sequence of tokens:
+    //
+    //     "Hello, ${name}!" => StringStart, StringFragment("Hello, "), Dollar,
+    //                          OpenCurly, Identifier("name"), CloseCurly,
+    //                          StringFragment("!"), StringEnd
+    StringStart, // Opening quote(s) of an interpolated string
+    StringFragment(String), // Literal text between interpolations, with escapes resolved
+    StringEnd, // Closing quote(s) of an interpolated string
 
     // Definitions
     Identifier(String),
 
-    Dollar, // String interpolation
+    // Documentation attached to the following declaration, from either a
+    // `/// line` or `/** block */` comment. Only the text inside the comment
+    // markers is kept.
+    DocComment(String),
+
+    // Keywords
+    Trait, // Trait function declarations
+    Fun, // Function declarations
+    Tuple, // Tuple type declarations
+    Enum, // Enum type declarations
+    Object, // Object type declarations
+    Impl, // Trait function implementations
+    Op, // Operator implementations
+    Val, // Value/property declarations
+    Public, // Visibility modifier
+    Private, // Visibility modifier
+    When, // Pattern matching blocks
+    If, // Conditionals
+    Else, // Conditionals
+    Throw, // Raising errors
+    Companion, // Companion type blocks
+    As, // Multi-use: receiver aliasing (|self as lhs|), casts
+    SelfValue, // The receiver of a member function (self)
+    Nil, // Monotype null
+    True, // Boolean literal
+    False, // Boolean literal
+
+    Dollar, // String interpolation, either $name or ${expr}
 
     Hash, // Companion references
 
-    Colon, // Multi-use: namespaced symbol access (::),
+    Colon, // Type annotations, named properties
+    DoubleColon, // Namespaced symbol access (::)
 
     Underscore, // Multi-use: numerical spacer (1_000), unused destructure, default case
 
     Dot, // Multi-use: property access/function calls, decimal point
+    Comma, // Separator: argument lists, tuple types, enum values, destructuring
 
-    Question, // Multi-use: nullish type, throws operator
-    Exclamation, // Multi-use: boolean not, throws operator
+    Question, // Nullish types and operators
+    Exclamation, // Boolean not
+    Throws, // Throws clause (?!)
 
     // Brackets
     OpenParen, // Multi use: function argument lists, keyword narrowing
@@ -37,25 +84,118 @@ pub enum TokenKind {
     CloseSquare, // Arrays: array type definition, index access
     OpenCurly, // Multi use: blocks, complex string interpolation
     CloseCurly, // Multi use: blocks, complex string interpolation
-    OpenAngle, // Multi-use: generic brackets, less than via < and <=
-    CloseAngle, // Multi-use: generic brackets, greater than via > and >=, case & lambda arrow (->)
+    OpenAngle, // Multi-use: generic brackets, less than
+    CloseAngle, // Multi-use: generic brackets, greater than
 
     // Operators
 
-    // Addition, subtraction via +, ++, += or -, --, -=
-    // Minus only: Case & lambda arrow (->)
-    Plus, Minus,
+    Plus, PlusPlus, PlusEquals, // Addition via +, ++ or +=
+    Minus, MinusMinus, MinusEquals, // Subtraction via -, -- or -=
+    Arrow, // Case & lambda arrow (->)
     Slash, // Multi-use: division, comments
     Asterisk, // Multiplication
-    Pipe, // Multi-use: boolean or, bitwise or, closure shadowing
-    Ampersand, // Multi-use: boolean and, bitwise and
+    Pipe, // Multi-use: bitwise or, closure shadowing
+    DoublePipe, // Boolean or
+    Ampersand, // Bitwise and
+    DoubleAmpersand, // Boolean and
 
-    // Multi-use: assignment (=), equality (==), or others (see other comments that include '=')
-    Equals,
+    Equals, // Assignment
+    DoubleEquals, // Equality
+    NotEquals, // Inequality
+    LessThanEquals, // Less than or equal to
+    GreaterThanEquals, // Greater than or equal to
 
+    Error, // Input which couldn't be lexed, only produced by tokenize_recovering()
+
+    #[allow(clippy::upper_case_acronyms)]
     EOF, // End of file
 }
 
+impl TokenKind {
+    /// Look up the keyword spelled by `word`, if there is one.
+    pub fn keyword(word: &str) -> Option<TokenKind> {
+        let kw = match word {
+            "trait" => TokenKind::Trait,
+            "fun" => TokenKind::Fun,
+            "tuple" => TokenKind::Tuple,
+            "enum" => TokenKind::Enum,
+            "object" => TokenKind::Object,
+            "impl" => TokenKind::Impl,
+            "op" => TokenKind::Op,
+            "val" => TokenKind::Val,
+            "public" => TokenKind::Public,
+            "private" => TokenKind::Private,
+            "when" => TokenKind::When,
+            "if" => TokenKind::If,
+            "else" => TokenKind::Else,
+            "throw" => TokenKind::Throw,
+            "companion" => TokenKind::Companion,
+            "as" => TokenKind::As,
+            "self" => TokenKind::SelfValue,
+            "nil" => TokenKind::Nil,
+            "true" => TokenKind::True,
+            "false" => TokenKind::False,
+            _ => return None,
+        };
+
+        Some(kw)
+    }
+
+    /// Is this token a reserved word?
+    pub fn is_keyword(&self) -> bool {
+        matches!(self,
+            TokenKind::Trait | TokenKind::Fun | TokenKind::Tuple | TokenKind::Enum
+            | TokenKind::Object | TokenKind::Impl | TokenKind::Op | TokenKind::Val
+            | TokenKind::Public | TokenKind::Private | TokenKind::When | TokenKind::If
+            | TokenKind::Else | TokenKind::Throw | TokenKind::Companion | TokenKind::As
+            | TokenKind::SelfValue | TokenKind::Nil | TokenKind::True | TokenKind::False)
+    }
+}
+
+/// An explicit type attached to the end of a number literal, e.g. `10u8` or
+/// `1.5f32`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NumberSuffix {
+    U8, U16, U32, U64, U128,
+    I8, I16, I32, I64, I128,
+    F32, F64,
+}
+
+impl NumberSuffix {
+    /// Parse the suffix spelled by `word`, if there is one.
+    pub fn from_suffix(word: &str) -> Option<NumberSuffix> {
+        let suffix = match word {
+            "u8" => NumberSuffix::U8,
+            "u16" => NumberSuffix::U16,
+            "u32" => NumberSuffix::U32,
+            "u64" => NumberSuffix::U64,
+            "u128" => NumberSuffix::U128,
+            "i8" => NumberSuffix::I8,
+            "i16" => NumberSuffix::I16,
+            "i32" => NumberSuffix::I32,
+            "i64" => NumberSuffix::I64,
+            "i128" => NumberSuffix::I128,
+            "f32" => NumberSuffix::F32,
+            "f64" => NumberSuffix::F64,
+            _ => return None,
+        };
+
+        Some(suffix)
+    }
+
+    /// Is this a signed integer (or float) suffix?
+    pub fn is_signed(self) -> bool {
+        !matches!(self,
+            NumberSuffix::U8 | NumberSuffix::U16 | NumberSuffix::U32
+            | NumberSuffix::U64 | NumberSuffix::U128)
+    }
+
+    /// Does this suffix make the number a floating point value?
+    pub fn is_float(self) -> bool {
+        matches!(self, NumberSuffix::F32 | NumberSuffix::F64)
+    }
+}
+
 impl From<String> for TokenKind {
     fn from(other: String) -> TokenKind {
         TokenKind::Identifier(other)
@@ -68,20 +208,20 @@ impl<'a> From<&'a str> for TokenKind {
     }
 }
 
-impl From<usize> for TokenKind {
-    fn from(other: usize) -> TokenKind {
-        TokenKind::Integer(other)
+impl From<u128> for TokenKind {
+    fn from(other: u128) -> TokenKind {
+        TokenKind::Integer { value: other, suffix: None }
     }
 }
 
 impl From<f64> for TokenKind {
     fn from(other: f64) -> TokenKind {
-        TokenKind::Decimal(other)
+        TokenKind::Decimal { value: other, suffix: None }
     }
 }
 
 /// Consumes bytes while a predicate evaluates to true.
-fn take_while<F>(data: &str, mut pred: F) -> Result<(&str, usize)>
+fn take_while<F>(data: &str, mut pred: F) -> Option<(&str, usize)>
     where F: FnMut(char) -> bool
 {
     let mut current_index = 0;
@@ -97,115 +237,612 @@ fn take_while<F>(data: &str, mut pred: F) -> Result<(&str, usize)>
     }
 
     if current_index == 0 {
-        Err("No Matches".into())
+        None
     } else {
-        Ok((&data[..current_index], current_index))
+        Some((&data[..current_index], current_index))
     }
 }
 
 fn tokenize_identifier(data: &str) -> Result<(TokenKind, usize)> {
-    // identifiers can't start with a number
+    // identifiers can't start with a number or a combining mark
     match data.chars().next() {
-        Some(ch) if ch.is_digit(10) => return bail!("Identifiers can't start with a number"),
-        None => return bail!(ErrorKind::UnexpectedEOF),
-        _ => {},
+        Some(ch) if is_identifier_start(ch) => {},
+        Some(ch) => return Err(Error::UnknownCharacter { ch, span: Span::new(0, ch.len_utf8()) }),
+        None => return Err(Error::UnexpectedEOF { span: Span::new(0, 0) }),
     }
 
-    let (got, bytes_read) = take_while(data, |ch| ch == '_' || ch.is_alphanumeric())?;
+    let (got, bytes_read) = take_while(data, is_identifier_continue)
+        .expect("We already know the first character is part of the identifier");
 
-    // TODO: Recognise keywords using a `match` statement here.
+    // identifiers which look the same should be the same symbol, regardless
+    // of how they were encoded
+    let got = if is_nfc(got) { got.to_string() } else { got.nfc().collect() };
 
-    let tok = TokenKind::Identifier(got.to_string());
+    let tok = match TokenKind::keyword(&got) {
+        Some(keyword) => keyword,
+        None => TokenKind::Identifier(got),
+    };
     Ok((tok, bytes_read))
 }
 
+/// Consume the digits of a number in the given radix, along with any `_`
+/// separators, pushing the digits themselves onto `digits`. The `offset` is
+/// where `data` starts relative to the beginning of the number, and is only
+/// used for error spans.
+fn take_digits(data: &str, radix: u32, offset: usize, digits: &mut String) -> Result<usize> {
+    let mut bytes_read = 0;
+    let separator = |ix: usize| Error::MisplacedSeparator { span: Span::new(offset + ix, offset + ix + 1) };
+
+    for ch in data.chars() {
+        if ch.is_digit(radix) {
+            digits.push(ch);
+        } else if ch.is_ascii_digit() {
+            let start = offset + bytes_read;
+            let span = Span::new(start, start + ch.len_utf8());
+            return Err(Error::InvalidDigit { ch, radix, span });
+        } else if ch == '_' && bytes_read == 0 {
+            // e.g. `0x_ff`
+            return Err(separator(0));
+        } else if ch != '_' {
+            break;
+        }
+
+        bytes_read += ch.len_utf8();
+    }
+
+    if data[..bytes_read].ends_with('_') {
+        return Err(separator(bytes_read - 1));
+    }
+
+    Ok(bytes_read)
+}
+
+/// Does `data` start with a digit, optionally preceded by one of `prefixes`?
+fn starts_with_digit(data: &str, prefixes: &[char]) -> bool {
+    let data = data.strip_prefix(prefixes).unwrap_or(data);
+    data.starts_with(|c: char| c.is_ascii_digit())
+}
+
 fn tokenize_number(data: &str) -> Result<(TokenKind, usize)> {
-    let mut seen_dot = false;
-
-    let (decimal, bytes_read) = take_while(data, |c| {
-        if c.is_digit(10) {
-            true
-        } else if c == '.' {
-            if !seen_dot {
-                seen_dot = true;
-                true
-            } else {
-                false
-            }
-        } else {
-            false
+    let (radix, mut current_index) = match data.get(..2) {
+        Some("0x") => (16, 2),
+        Some("0o") => (8, 2),
+        Some("0b") => (2, 2),
+        _ => (10, 0),
+    };
+
+    let mut digits = String::new();
+    current_index += take_digits(&data[current_index..], radix, current_index, &mut digits)?;
+    let mut is_decimal = false;
+
+    if radix == 10 {
+        if data[current_index..].starts_with('.') && starts_with_digit(&data[current_index + 1..], &[]) {
+            is_decimal = true;
+            digits.push('.');
+            current_index += 1;
+            current_index += take_digits(&data[current_index..], radix, current_index, &mut digits)?;
         }
-    })?;
 
-    if seen_dot {
-        let n: f64 = decimal.parse()?;
-        Ok((TokenKind::Decimal(n), bytes_read))
+        let rest = &data[current_index..];
+        if rest.starts_with(['e', 'E']) && starts_with_digit(&rest[1..], &['+', '-']) {
+            is_decimal = true;
+            let sign_length = if rest[1..].starts_with(['+', '-']) { 1 } else { 0 };
+            digits.push_str(&rest[..1 + sign_length]);
+            current_index += 1 + sign_length;
+            current_index += take_digits(&data[current_index..], radix, current_index, &mut digits)?;
+        } else if rest.starts_with(['e', 'E']) && rest[1..].starts_with(['+', '-']) {
+            let expected_at = current_index + 2;
+            return Err(Error::MissingDigits { span: Span::new(expected_at, expected_at) });
+        }
+    }
+
+    if digits.is_empty() {
+        return Err(Error::MissingDigits { span: Span::new(current_index, current_index) });
+    }
+
+    let suffix = match take_while(&data[current_index..], is_identifier_continue) {
+        Some((word, bytes_read)) => {
+            let suffix = match NumberSuffix::from_suffix(word) {
+                // only base 10 numbers can be floats
+                Some(suffix) if suffix.is_float() && radix == 10 => suffix,
+                Some(suffix) if !suffix.is_float() && !is_decimal => suffix,
+                _ => {
+                    let span = Span::new(current_index, current_index + bytes_read);
+                    return Err(Error::InvalidSuffix { suffix: word.to_string(), span });
+                },
+            };
+            current_index += bytes_read;
+            Some(suffix)
+        },
+        None => None,
+    };
+
+    if is_decimal || suffix.is_some_and(NumberSuffix::is_float) {
+        let value: f64 = digits.parse()
+            .expect("The digits have already been checked");
+        Ok((TokenKind::Decimal { value, suffix }, current_index))
     } else {
-        let n: usize = decimal.parse()?;
-        Ok((TokenKind::Integer(n), bytes_read))
+        // the digits have already been checked, so this can only overflow
+        let value = u128::from_str_radix(&digits, radix)
+            .map_err(|_| Error::IntegerOverflow { span: Span::new(0, current_index) })?;
+        Ok((TokenKind::Integer { value, suffix }, current_index))
     }
 }
 
 fn skip_whitespace(data: &str) -> usize {
     match take_while(data, |ch| ch.is_whitespace()) {
-        Ok((_, bytes_skipped)) => bytes_skipped,
+        Some((_, bytes_skipped)) => bytes_skipped,
         _ => 0,
     }
 }
 
-fn skip_comments(src: &str) -> usize {
-    let pairs = [("//", "\n"), ("/*", "*/")];
+/// Does `src` start with a doc comment (`///` or `/**`)? Runs of slashes or
+/// asterisks (e.g. `////` or `/***`) are regular comments, as is `/**/`.
+fn starts_doc_comment(src: &str) -> bool {
+    (src.starts_with("///") && !src.starts_with("////"))
+        || (src.starts_with("/**") && !src.starts_with("/***") && !src.starts_with("/**/"))
+}
+
+/// The number of bytes taken up by a line comment, including the newline.
+fn line_comment_length(src: &str) -> usize {
+    match src.find('\n') {
+        Some(ix) => ix + 1,
+        None => src.len(),
+    }
+}
+
+/// The number of bytes taken up by a (possibly nested) block comment.
+fn block_comment_length(src: &str) -> Result<usize> {
+    let mut depth = 0;
+    let mut current_index = 0;
+
+    while let Some(ch) = src[current_index..].chars().next() {
+        let remaining = &src[current_index..];
+
+        if remaining.starts_with("/*") {
+            depth += 1;
+            current_index += 2;
+        } else if remaining.starts_with("*/") {
+            depth -= 1;
+            current_index += 2;
+
+            if depth == 0 {
+                return Ok(current_index);
+            }
+        } else {
+            current_index += ch.len_utf8();
+        }
+    }
+
+    Err(Error::UnterminatedComment { span: Span::new(0, src.len()) })
+}
+
+fn skip_comments(src: &str) -> Result<usize> {
+    if starts_doc_comment(src) {
+        // doc comments are tokens in their own right
+        Ok(0)
+    } else if src.starts_with("//") {
+        Ok(line_comment_length(src))
+    } else if src.starts_with("/*") {
+        block_comment_length(src)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Lex a single piece of trivia (whitespace, a newline or a comment) from
+/// the start of `data`, if there is one.
+fn tokenize_trivia(data: &str) -> Result<Option<(TriviaKind, usize)>> {
+    if data.starts_with('\n') {
+        return Ok(Some((TriviaKind::Newline, 1)));
+    } else if data.starts_with("\r\n") {
+        return Ok(Some((TriviaKind::Newline, 2)));
+    }
+
+    let whitespace = &data[..skip_whitespace(data)];
+    let before_newline = match whitespace.find('\n') {
+        Some(ix) => whitespace[..ix].strip_suffix('\r').unwrap_or(&whitespace[..ix]),
+        None => whitespace,
+    };
+
+    if !before_newline.is_empty() {
+        return Ok(Some((TriviaKind::Whitespace, before_newline.len())));
+    }
+
+    let comment = &data[..skip_comments(data)?];
+
+    if comment.is_empty() {
+        Ok(None)
+    } else if comment.starts_with("//") {
+        let line = comment.trim_end_matches('\n');
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        Ok(Some((TriviaKind::LineComment, line.len())))
+    } else {
+        Ok(Some((TriviaKind::BlockComment, comment.len())))
+    }
+}
+
+/// Lex a `/// line` or `/** block */` doc comment.
+fn tokenize_doc_comment(data: &str) -> Result<(TokenKind, usize)> {
+    if data.starts_with("///") {
+        let length = line_comment_length(data);
+        let text = data[3..length].trim_end_matches(['\n', '\r']);
+        Ok((TokenKind::DocComment(text.to_string()), text.len() + 3))
+    } else {
+        let length = block_comment_length(data)?;
+        let text = &data[3..length - 2];
+        Ok((TokenKind::DocComment(text.to_string()), length))
+    }
+}
+
+/// Can `ch` start an identifier? This follows UAX #31, except that
+/// identifiers may also start with an underscore.
+fn is_identifier_start(ch: char) -> bool {
+    ch == '_' || ch.is_xid_start()
+}
+
+/// Can `ch` appear after the first character of an identifier?
+fn is_identifier_continue(ch: char) -> bool {
+    ch.is_xid_continue()
+}
+
+/// Does `data` continue with something that could be part of an identifier?
+fn starts_identifier(data: &str) -> bool {
+    data.chars().next().is_some_and(is_identifier_continue)
+}
+
+const QUOTE: &str = "\"";
+const TRIPLE_QUOTE: &str = "\"\"\"";
+
+/// Does `data` start an interpolation (`${expr}` or `$name`) inside a string?
+fn starts_interpolation(data: &str) -> bool {
+    let mut chars = data.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some('$'), Some(next)) => next == '{' || is_identifier_start(next),
+        _ => false,
+    }
+}
+
+/// Resolve the escape sequence at the start of `data` (starting with the
+/// backslash), returning the escaped character and how many bytes were used.
+fn tokenize_escape(data: &str) -> Result<(char, usize)> {
+    let next = match data[1..].chars().next() {
+        Some(c) => c,
+        None => return Err(Error::UnterminatedString { span: Span::new(0, 1) }),
+    };
+    let invalid = |sequence: &str| Error::InvalidEscape {
+        sequence: sequence.to_string(),
+        span: Span::new(0, sequence.len()),
+    };
+
+    let ch = match next {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        '\\' => '\\',
+        '"' => '"',
+        '\'' => '\'',
+        '$' => '$',
+        'u' => {
+            if !data[2..].starts_with('{') {
+                return Err(invalid(&data[..2]));
+            }
+            // only look as far as the hex digits, so a missing `}` doesn't
+            // swallow the rest of the file
+            let close = data[3..].find(|c: char| !c.is_ascii_hexdigit())
+                .map_or(data.len(), |ix| 3 + ix);
+            if !data[close..].starts_with('}') {
+                return Err(Error::UnterminatedEscape { span: Span::new(0, close) });
+            }
+            let sequence = &data[..=close];
+            let digits = &data[3..close];
+
+            let valid_length = !digits.is_empty() && digits.len() <= 6;
+            let ch = u32::from_str_radix(digits, 16).ok()
+                .filter(|_| valid_length)
+                .and_then(char::from_u32);
+
+            return match ch {
+                Some(ch) => Ok((ch, sequence.len())),
+                None => Err(invalid(sequence)),
+            };
+        },
+        other => return Err(invalid(&data[..1 + other.len_utf8()])),
+    };
+
+    Ok((ch, 1 + next.len_utf8()))
+}
+
+/// Read the literal text of a string up until its closing quote(s) or the
+/// next interpolation, resolving any escape sequences along the way.
+fn take_string_text(data: &str, triple: bool) -> Result<(String, usize)> {
+    let terminator = if triple { TRIPLE_QUOTE } else { QUOTE };
+    let mut text = String::new();
+    let mut current_index = 0;
+
+    loop {
+        let remaining = &data[current_index..];
+
+        if remaining.starts_with(terminator) || starts_interpolation(remaining) {
+            return Ok((text, current_index));
+        }
 
-    for &(pattern, matcher) in &pairs {
-        if src.starts_with(pattern) {
-            let leftovers = skip_until(src, matcher);
-            return src.len() - leftovers.len();
+        match remaining.chars().next() {
+            None => return Err(Error::UnterminatedString { span: Span::new(0, current_index) }),
+            Some('\n') if !triple => {
+                return Err(Error::UnterminatedString { span: Span::new(0, current_index) });
+            },
+            Some('\\') => {
+                let (ch, bytes_read) = tokenize_escape(remaining)
+                    .map_err(|e| e.offset(current_index))?;
+                text.push(ch);
+                current_index += bytes_read;
+            },
+            Some(ch) => {
+                text.push(ch);
+                current_index += ch.len_utf8();
+            },
         }
     }
+}
+
+/// Lex the start of a string literal. Strings without any interpolation are
+/// read in one go, otherwise only the opening quotes are consumed and the
+/// rest is read piece by piece with `tokenize_string_segment()`.
+fn tokenize_string(data: &str) -> Result<(TokenKind, usize)> {
+    let triple = data.starts_with(TRIPLE_QUOTE);
+    let quote = if triple { TRIPLE_QUOTE } else { QUOTE };
+
+    let (text, bytes_read) = take_string_text(&data[quote.len()..], triple)
+        .map_err(|e| from_opening_quote(e.offset(quote.len()), 0))?;
+    let rest = &data[quote.len() + bytes_read..];
+
+    if rest.starts_with(quote) {
+        let length = quote.len() + bytes_read + quote.len();
+        Ok((TokenKind::QuotedString(text), length))
+    } else {
+        Ok((TokenKind::StringStart, quote.len()))
+    }
+}
+
+/// Unterminated strings are reported from their opening quote, which is at
+/// `start`, rather than from wherever the lexer gave up.
+fn from_opening_quote(error: Error, start: usize) -> Error {
+    match error {
+        Error::UnterminatedString { span } => {
+            Error::UnterminatedString { span: Span::new(start, span.end()) }
+        },
+        other => other,
+    }
+}
+
+/// Lex the next piece of an interpolated string.
+fn tokenize_string_segment(data: &str, triple: bool) -> Result<(TokenKind, usize)> {
+    let quote = if triple { TRIPLE_QUOTE } else { QUOTE };
+
+    if data.starts_with(quote) {
+        Ok((TokenKind::StringEnd, quote.len()))
+    } else if starts_interpolation(data) {
+        Ok((TokenKind::Dollar, 1))
+    } else {
+        let (text, bytes_read) = take_string_text(data, triple)?;
+        Ok((TokenKind::StringFragment(text), bytes_read))
+    }
+}
+
+/// Resolve an escape sequence in a byte literal, which may also be a `\xNN`
+/// hex escape for any byte.
+fn tokenize_byte_escape(data: &str) -> Result<(u8, usize)> {
+    if !data[1..].starts_with('x') {
+        let (ch, bytes_read) = tokenize_escape(data)?;
+        return to_byte(ch, Span::new(0, bytes_read)).map(|byte| (byte, bytes_read));
+    }
+
+    match data.get(2..4).filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit())) {
+        Some(digits) => {
+            let byte = u8::from_str_radix(digits, 16).expect("Already checked the digits");
+            Ok((byte, 4))
+        },
+        None => {
+            let digits: usize = data[2..].chars()
+                .take(2)
+                .take_while(char::is_ascii_alphanumeric)
+                .map(char::len_utf8)
+                .sum();
+            let sequence = &data[..2 + digits];
+            let span = Span::new(0, sequence.len());
+            Err(Error::InvalidEscape { sequence: sequence.to_string(), span })
+        },
+    }
+}
+
+fn to_byte(ch: char, span: Span) -> Result<u8> {
+    if ch.is_ascii() {
+        Ok(ch as u8)
+    } else {
+        Err(Error::NonAsciiByte { ch, span })
+    }
+}
+
+/// Read a single (possibly escaped) character from the start of `data`.
+fn take_char(data: &str) -> Result<(char, usize)> {
+    match data.chars().next() {
+        Some('\\') => tokenize_escape(data),
+        Some(ch) => Ok((ch, ch.len_utf8())),
+        None => Err(Error::UnexpectedEOF { span: Span::new(0, 0) }),
+    }
+}
 
-    0
+/// Read a single (possibly escaped) byte from the start of `data`.
+fn take_byte(data: &str) -> Result<(u8, usize)> {
+    match data.chars().next() {
+        Some('\\') => tokenize_byte_escape(data),
+        Some(ch) => to_byte(ch, Span::new(0, ch.len_utf8())).map(|byte| (byte, ch.len_utf8())),
+        None => Err(Error::UnexpectedEOF { span: Span::new(0, 0) }),
+    }
 }
 
-fn skip_until<'a>(mut src: &'a str, pattern: &str) -> &'a str {
-    while !src.is_empty() && !src.starts_with(pattern) {
-        let next_char_size = src.chars().next().expect("The string isn't empty").len_utf8();
-        src = &src[next_char_size..];
+/// Lex a character literal which starts with the `'` at the start of
+/// `data`, using `read` to get the character itself. Returns the character
+/// and the length of the whole literal.
+fn tokenize_quoted_char<T, F>(data: &str, read: F) -> Result<(T, usize)>
+    where F: Fn(&str) -> Result<(T, usize)>
+{
+    let inner = &data[1..];
+
+    match inner.chars().next() {
+        None | Some('\n') => return Err(Error::UnterminatedCharLiteral { span: Span::new(0, 1) }),
+        Some('\'') => return Err(Error::EmptyCharLiteral { span: Span::new(0, 2) }),
+        _ => {},
+    }
+
+    let (value, bytes_read) = read(inner).map_err(|e| match e {
+        // a backslash right at the end of the input
+        Error::UnterminatedString { .. } => Error::UnterminatedCharLiteral { span: Span::new(0, data.len()) },
+        other => other.offset(1),
+    })?;
+    let rest = &inner[bytes_read..];
+
+    if rest.starts_with('\'') {
+        return Ok((value, 1 + bytes_read + 1));
     }
 
-    &src[pattern.len()..]
+    // if the literal is closed later on the same line, it's just too long
+    let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+    match line.find('\'') {
+        Some(ix) => Err(Error::OverlongCharLiteral { span: Span::new(0, 1 + bytes_read + ix + 1) }),
+        None => Err(Error::UnterminatedCharLiteral { span: Span::new(0, 1 + bytes_read) }),
+    }
 }
 
-fn skip(src: &str) -> usize {
-    let mut remaining = src;
+/// Lex a `b'c'` byte literal or `b"..."` byte string.
+fn tokenize_byte_literal(data: &str) -> Result<(TokenKind, usize)> {
+    if data[1..].starts_with('\'') {
+        let (byte, length) = tokenize_quoted_char(&data[1..], take_byte)
+            .map_err(|e| e.offset(1))?;
+        return Ok((TokenKind::Byte(byte), 1 + length));
+    }
+
+    let mut bytes = Vec::new();
+    let mut current_index = 1 + QUOTE.len();
 
     loop {
-        let ws = skip_whitespace(remaining);
-        remaining = &remaining[ws..];
-        let comments = skip_comments(remaining);
-        remaining = &remaining[comments..];
+        let remaining = &data[current_index..];
+
+        match remaining.chars().next() {
+            Some('"') => return Ok((TokenKind::ByteString(bytes), current_index + QUOTE.len())),
+            None | Some('\n') => {
+                return Err(Error::UnterminatedString { span: Span::new(0, current_index) });
+            },
+            Some(_) => {
+                let (byte, bytes_read) = take_byte(remaining)
+                    .map_err(|e| e.offset(current_index))?;
+                bytes.push(byte);
+                current_index += bytes_read;
+            },
+        }
+    }
+}
+
+/// The number of bytes taken up by a broken character or byte literal,
+/// where `data` starts at its opening quote. We assume the literal ends at
+/// the next quote on the same line, if there is one.
+fn char_recovery_length(data: &str) -> usize {
+    let line_end = data.find('\n').unwrap_or(data.len());
+
+    match data[1..line_end].find('\'') {
+        Some(ix) => 1 + ix + 1,
+        None => line_end,
+    }
+}
+
+/// The number of bytes up to and including the end of a broken string,
+/// where `data` starts just after its opening quote(s). Single-line strings
+/// are cut off at the end of the line if they aren't closed before then.
+fn string_recovery_length(data: &str, triple: bool) -> usize {
+    let terminator = if triple { TRIPLE_QUOTE } else { QUOTE };
+    let mut chars = data.char_indices();
+
+    while let Some((ix, ch)) = chars.next() {
+        if data[ix..].starts_with(terminator) {
+            return ix + terminator.len();
+        } else if ch == '\n' && !triple {
+            return ix;
+        } else if ch == '\\' {
+            chars.next();
+        }
+    }
+
+    data.len()
+}
 
-        if ws + comments == 0 {
-            return src.len() - remaining.len();
+/// The number of bytes taken up by a run of characters which can't start any
+/// token, stopping at whitespace.
+fn unknown_run_length(data: &str) -> usize {
+    let mut length = 0;
+
+    for ch in data.chars() {
+        let rest = &data[length..];
+        if length > 0 && (ch.is_whitespace() || tokenize_single_token(rest).is_ok()) {
+            break;
         }
+
+        length += ch.len_utf8();
     }
+
+    length
+}
+
+/// Operators spelled with more than one character. These are checked before
+/// any single character tokens so the lexer always takes the longest match.
+const COMPOUND_OPERATORS: &[(&str, TokenKind)] = &[
+    ("==", TokenKind::DoubleEquals),
+    ("!=", TokenKind::NotEquals),
+    ("<=", TokenKind::LessThanEquals),
+    (">=", TokenKind::GreaterThanEquals),
+    ("->", TokenKind::Arrow),
+    ("::", TokenKind::DoubleColon),
+    ("+=", TokenKind::PlusEquals),
+    ("-=", TokenKind::MinusEquals),
+    ("++", TokenKind::PlusPlus),
+    ("--", TokenKind::MinusMinus),
+    ("&&", TokenKind::DoubleAmpersand),
+    ("||", TokenKind::DoublePipe),
+    ("?!", TokenKind::Throws),
+];
+
+/// Try to lex an operator made up of several characters.
+fn tokenize_compound_operator(data: &str) -> Option<(TokenKind, usize)> {
+    COMPOUND_OPERATORS.iter()
+        .find(|(pattern, _)| data.starts_with(pattern))
+        .map(|(pattern, kind)| (kind.clone(), pattern.len()))
 }
 
 /// Try to lex a single token from the input stream.
-pub fn tokenize_single_token(data: &str) -> Result<(TokenKind, usize)> {
+fn tokenize_single_token(data: &str) -> Result<(TokenKind, usize)> {
     let next = match data.chars().next() {
         Some(c) => c,
-        None => bail!(ErrorKind::UnexpectedEOF),
+        None => return Err(Error::UnexpectedEOF { span: Span::new(0, 0) }),
     };
 
+    if let Some(compound) = tokenize_compound_operator(data) {
+        return Ok(compound);
+    }
+
     let (tok, length) = match next {
+        '.' if starts_with_digit(&data[1..], &[]) => tokenize_number(data)?,
         '.' => (TokenKind::Dot, 1),
+        ',' => (TokenKind::Comma, 1),
         '=' => (TokenKind::Equals, 1),
         '+' => (TokenKind::Plus, 1),
         '-' => (TokenKind::Minus, 1),
         '*' => (TokenKind::Asterisk, 1),
+        '/' if starts_doc_comment(data) => tokenize_doc_comment(data)?,
         '/' => (TokenKind::Slash, 1),
         '|' => (TokenKind::Pipe, 1),
-        '_' => (TokenKind::Underscore, 1),
+        '_' if !starts_identifier(&data[1..]) => (TokenKind::Underscore, 1),
         '$' => (TokenKind::Dollar, 1),
         ':' => (TokenKind::Colon, 1),
         '#' => (TokenKind::Hash, 1),
@@ -220,66 +857,278 @@ pub fn tokenize_single_token(data: &str) -> Result<(TokenKind, usize)> {
         '>' => (TokenKind::CloseAngle, 1),
         '!' => (TokenKind::Exclamation, 1),
         '?' => (TokenKind::Question, 1),
-        '0' ..= '9' => tokenize_number(data).chain_err(|| "Couldn't tokenize a number")?,
-        c @ '_' | c if c.is_alphabetic() => tokenize_identifier(data)
-            .chain_err(|| "Couldn't tokenize an identifier")?,
-        other => bail!(ErrorKind::UnknownCharacter(other)),
+        '"' => tokenize_string(data)?,
+        '\'' => {
+            let (ch, length) = tokenize_quoted_char(data, take_char)?;
+            (TokenKind::Character(ch), length)
+        },
+        'b' if data[1..].starts_with(['\'', '"']) => tokenize_byte_literal(data)?,
+        '0' ..= '9' => tokenize_number(data)?,
+        c if is_identifier_start(c) => tokenize_identifier(data)?,
+        other => {
+            return Err(Error::UnknownCharacter { ch: other, span: Span::new(0, other.len_utf8()) });
+        },
     };
 
     Ok((tok, length))
 }
 
-struct Tokenizer<'a> {
+/// Can a `.` directly after `previous` start a decimal like `.05`? Not if
+/// `previous` can end an expression, where it's a member access (`pair.0`),
+/// or if it's another `.` (`1..5`).
+fn dot_can_start_decimal_after(previous: &TokenKind) -> bool {
+    !matches!(
+        previous,
+        TokenKind::Integer { .. }
+            | TokenKind::Decimal { .. }
+            | TokenKind::QuotedString(_)
+            | TokenKind::Character(_)
+            | TokenKind::Byte(_)
+            | TokenKind::ByteString(_)
+            | TokenKind::StringEnd
+            | TokenKind::Identifier(_)
+            | TokenKind::SelfValue
+            | TokenKind::Nil
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Underscore
+            | TokenKind::Question
+            | TokenKind::Exclamation
+            | TokenKind::CloseParen
+            | TokenKind::CloseSquare
+            | TokenKind::CloseCurly
+            | TokenKind::Dot
+    )
+}
+
+/// What the `Tokenizer` is currently in the middle of.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    /// The literal text of an interpolated string which started at `start`.
+    String { triple: bool, start: usize },
+    /// A `$name` interpolation, which is always a single identifier.
+    Variable,
+    /// A `${expr}` interpolation with `depth` unclosed curly braces.
+    Interpolation { depth: usize },
+}
+
+pub(crate) struct Tokenizer<'a> {
     current_index: usize,
     remaining_text: &'a str,
+    modes: Vec<Mode>,
+    /// Whether a `.` followed by a digit starts a decimal (`.05`) rather than
+    /// being a `Dot`, which depends on the token before it.
+    dot_starts_decimal: bool,
+    /// Trivia skipped since the last token, only kept in lossless mode.
+    trivia: Option<Vec<RawTrivia>>,
 }
 
 impl<'a> Tokenizer<'a> {
-    fn new(src: &str) -> Tokenizer {
+    fn new(src: &str) -> Tokenizer<'_> {
         Tokenizer {
             current_index: 0,
             remaining_text: src,
+            modes: Vec::new(),
+            dot_starts_decimal: true,
+            trivia: None,
+        }
+    }
+
+    /// Create a `Tokenizer` which starts part way through `src`, at a point
+    /// where no strings are open, just after the token `previous`.
+    pub(crate) fn starting_at(src: &'a str, offset: usize, previous: Option<&TokenKind>) -> Tokenizer<'a> {
+        Tokenizer {
+            current_index: offset,
+            remaining_text: &src[offset..],
+            dot_starts_decimal: previous.is_none_or(dot_can_start_decimal_after),
+            ..Tokenizer::new(src)
+        }
+    }
+
+    /// Create a `Tokenizer` which remembers the trivia it skips.
+    fn lossless(src: &str) -> Tokenizer<'_> {
+        Tokenizer {
+            trivia: Some(Vec::new()),
+            ..Tokenizer::new(src)
         }
     }
 
-    fn next_token(&mut self) -> Result<Option<(TokenKind, usize, usize)>> {
-        self.skip_whitespace();
+    /// Take the trivia which has been skipped since this was last called.
+    fn take_trivia(&mut self) -> Vec<RawTrivia> {
+        self.trivia.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub(crate) fn next_token(&mut self) -> Result<Option<(TokenKind, usize, usize)>> {
+        if !self.in_string_text() {
+            self.skip_whitespace()?;
+        }
 
         if self.remaining_text.is_empty() {
-            Ok(None)
+            match self.open_string() {
+                Some(start) => {
+                    let span = Span::new(start, self.current_index);
+                    Err(Error::UnterminatedString { span })
+                },
+                None => Ok(None),
+            }
         } else {
             let start = self.current_index;
             let tok = self._next_token()
-                .chain_err(|| ErrorKind::MessageWithLocation(self.current_index,
-                                                             "Couldn't read the next token"))?;
+                .map_err(|e| self.locate(e, start))?;
             let end = self.current_index;
             Ok(Some((tok, start, end)))
         }
     }
 
-    fn skip_whitespace(&mut self) {
-        let skipped = skip(self.remaining_text);
-        self.chomp(skipped);
+    fn skip_whitespace(&mut self) -> Result<()> {
+        loop {
+            let start = self.current_index;
+            let trivia = tokenize_trivia(self.remaining_text)
+                .map_err(|e| e.offset(start))?;
+
+            let (kind, bytes_read) = match trivia {
+                Some(t) => t,
+                None => return Ok(()),
+            };
+            self.chomp(bytes_read);
+
+            if let Some(trivia) = self.trivia.as_mut() {
+                trivia.push((kind, start, self.current_index));
+            }
+        }
     }
 
     fn _next_token(&mut self) -> Result<TokenKind> {
-        let (tok, bytes_read) = tokenize_single_token(self.remaining_text)?;
+        let (tok, bytes_read) = match self.modes.last() {
+            Some(&Mode::String { triple, .. }) => tokenize_string_segment(self.remaining_text, triple)?,
+            // `$b"` is the variable `b` followed by the end of the string,
+            // not the start of a byte string
+            Some(Mode::Variable) => tokenize_identifier(self.remaining_text)?,
+            _ if !self.dot_starts_decimal && self.remaining_text.starts_with('.') => (TokenKind::Dot, 1),
+            _ => tokenize_single_token(self.remaining_text)?,
+        };
+        let start = self.current_index;
         self.chomp(bytes_read);
+        self.update_mode(&tok, start);
+        self.dot_starts_decimal = dot_can_start_decimal_after(&tok);
 
         Ok(tok)
     }
 
+    /// Move an error from lexing the token starting at `start` so its span is
+    /// relative to the start of the input.
+    fn locate(&self, error: Error, start: usize) -> Error {
+        let error = error.offset(start);
+
+        match self.modes.last() {
+            Some(&Mode::String { start: string_start, .. }) => from_opening_quote(error, string_start),
+            _ => error,
+        }
+    }
+
+    /// Skip past the input which caused `next_token()` to fail, returning
+    /// the number of bytes skipped. This makes a best guess at where the
+    /// broken token ends so lexing can carry on from there.
+    fn recover(&mut self) -> usize {
+        let data = self.remaining_text;
+
+        let length = if let Some(&Mode::String { triple, .. }) = self.modes.last() {
+            // give up on whatever is left of the string. If a newline cut it
+            // off right here, skip the newline so the error isn't empty.
+            self.modes.pop();
+            match string_recovery_length(data, triple) {
+                0 if data.starts_with('\n') => 1,
+                length => length,
+            }
+        } else if data.starts_with('"') {
+            let triple = data.starts_with(TRIPLE_QUOTE);
+            let quote = if triple { TRIPLE_QUOTE } else { QUOTE };
+            quote.len() + string_recovery_length(&data[quote.len()..], triple)
+        } else if let Some(rest) = data.strip_prefix("b\"") {
+            2 + string_recovery_length(rest, false)
+        } else if data.starts_with("b'") {
+            1 + char_recovery_length(&data[1..])
+        } else if data.starts_with('\'') {
+            char_recovery_length(data)
+        } else if data.starts_with("/*") {
+            // unterminated block comments run to the end of the file anyway
+            data.len()
+        } else if starts_identifier(data) || starts_with_digit(data, &['.']) {
+            take_while(data, |c| c == '.' || is_identifier_continue(c))
+                .map(|(_, bytes_read)| bytes_read)
+                .unwrap_or_default()
+        } else {
+            unknown_run_length(data)
+        };
+
+        self.chomp(length);
+        length
+    }
+
+    /// Keep track of entering and leaving strings and interpolations after
+    /// reading the token, `tok`, which started at `start`.
+    fn update_mode(&mut self, tok: &TokenKind, start: usize) {
+        match (self.modes.last_mut(), tok) {
+            (Some(Mode::String { .. }), TokenKind::StringEnd) => {
+                self.modes.pop();
+            },
+            (Some(Mode::String { .. }), TokenKind::Dollar) => {
+                let mode = if self.remaining_text.starts_with('{') {
+                    Mode::Interpolation { depth: 0 }
+                } else {
+                    Mode::Variable
+                };
+                self.modes.push(mode);
+            },
+            (Some(Mode::Variable), _) => {
+                self.modes.pop();
+            },
+            (Some(Mode::Interpolation { depth }), TokenKind::OpenCurly) => *depth += 1,
+            (Some(Mode::Interpolation { depth }), TokenKind::CloseCurly) => {
+                *depth -= 1;
+                if *depth == 0 {
+                    self.modes.pop();
+                }
+            },
+            (_, TokenKind::StringStart) => {
+                let triple = self.current_index - start == TRIPLE_QUOTE.len();
+                self.modes.push(Mode::String { triple, start });
+            },
+            _ => {},
+        }
+    }
+
+    /// Are we outside of any strings or interpolations?
+    pub(crate) fn at_top_level(&self) -> bool {
+        self.modes.is_empty()
+    }
+
+    /// Are we reading the literal text of a string, where whitespace and
+    /// comments are significant?
+    fn in_string_text(&self) -> bool {
+        matches!(self.modes.last(), Some(Mode::String { .. }))
+    }
+
+    /// The starting index of the innermost string we're part way through.
+    fn open_string(&self) -> Option<usize> {
+        self.modes.iter().rev().find_map(|mode| match *mode {
+            Mode::String { start, .. } => Some(start),
+            _ => None,
+        })
+    }
+
     fn chomp(&mut self, num_bytes: usize) {
         self.remaining_text = &self.remaining_text[num_bytes..];
         self.current_index += num_bytes;
     }
 }
 
-/// Turn a string of valid Delphi code into a list of tokens, including the
+/// Turn a string of Maple source code into a list of tokens, including the
 /// location of that token's start and end point in the original source code.
 ///
 /// Note the token indices represent the half-open interval `[start, end)`,
-/// equivalent to `start .. end` in Rust.
+/// equivalent to `start .. end` in Rust. Any error's span is relative to the
+/// start of `src`, use `FileMap::register_error()` to get a proper one.
 pub fn tokenize(src: &str) -> Result<Vec<(TokenKind, usize, usize)>> {
     let mut tokenizer = Tokenizer::new(src);
     let mut tokens = Vec::new();
@@ -291,43 +1140,238 @@ pub fn tokenize(src: &str) -> Result<Vec<(TokenKind, usize, usize)>> {
     Ok(tokens)
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Token {
-    /// The token's location relative to the rest of the files being
-    /// processed.
-    pub span: Span,
-    /// What kind of token is this?
-    pub kind: TokenKind,
+/// Split the trivia between two tokens into the first token's trailing
+/// trivia (everything up to the end of its line) and the second token's
+/// leading trivia.
+fn split_trivia(mut trivia: Vec<RawTrivia>) -> (Vec<RawTrivia>, Vec<RawTrivia>) {
+    let end_of_line = trivia.iter()
+        .position(|&(kind, _, _)| kind == TriviaKind::Newline)
+        .unwrap_or(trivia.len());
+    let leading = trivia.split_off(end_of_line);
+
+    (trivia, leading)
 }
 
-impl Token {
-    /// Create a new token out of a `Span` and something which can be turned
-    /// into a `TokenKind`.
-    pub fn new<K: Into<TokenKind>>(span: Span, kind: K) -> Token {
-        let kind = kind.into();
-        Token { span, kind }
+/// A lazy stream of `Token`s from a `FileMap`, created with
+/// `FileMap::tokens()`.
+///
+/// Tokens are only lexed as they're asked for, with `peek()` and `peek_nth()`
+/// reading as far ahead as necessary. Once an error is hit it is yielded and
+/// the stream ends.
+pub struct Tokens<'a> {
+    filemap: &'a FileMap,
+    tokenizer: Tokenizer<'a>,
+    lookahead: VecDeque<Result<Token>>,
+    finished: bool,
+}
+
+impl<'a> Tokens<'a> {
+    pub(crate) fn new(filemap: &'a FileMap) -> Tokens<'a> {
+        Tokens {
+            filemap,
+            tokenizer: Tokenizer::new(filemap.contents()),
+            lookahead: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Look at the next token without consuming it.
+    pub fn peek(&mut self) -> Option<&Result<Token>> {
+        self.peek_nth(0)
+    }
+
+    /// Look `n` tokens ahead without consuming anything, where `peek_nth(0)`
+    /// is the same as `peek()`.
+    pub fn peek_nth(&mut self, n: usize) -> Option<&Result<Token>> {
+        while self.lookahead.len() <= n && !self.finished {
+            self.read_token();
+        }
+
+        self.lookahead.get(n)
+    }
+
+    fn read_token(&mut self) {
+        match self.tokenizer.next_token() {
+            Ok(Some((kind, start, end))) => {
+                let span = self.filemap.insert_span(start, end);
+                self.lookahead.push_back(Ok(Token::new(span, kind)));
+            },
+            Ok(None) => self.finished = true,
+            Err(e) => {
+                self.lookahead.push_back(Err(self.filemap.register_error(e)));
+                self.finished = true;
+            },
+        }
     }
 }
 
-impl<T> From<T> for Token
-    where T: Into<TokenKind> {
-    fn from(other: T) -> Token {
-        Token::new(Span::dummy(), other)
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.peek();
+        self.lookahead.pop_front()
     }
 }
 
+/// Turn a string of Maple code into a list of tokens like `tokenize()`, but
+/// carrying on past any errors so they can all be reported at once.
+///
+/// Whenever something can't be lexed an `Error` token is emitted spanning
+/// the bad input (as best as we can tell) and lexing continues after it.
+/// Every error is returned as well, with spans relative to the start of
+/// `src`.
+pub fn tokenize_recovering(src: &str) -> (Vec<(TokenKind, usize, usize)>, Vec<Error>) {
+    let mut tokenizer = Tokenizer::new(src);
+    let mut tokens = Vec::new();
+    let mut diagnostics = Vec::new();
 
-#[cfg(test)]
-mod test {
-    use crate::errors::ErrorKind;
-    use crate::lex::{skip_comments, skip_whitespace, tokenize, tokenize_identifier, TokenKind};
-    macro_rules! lexer_test {
-        (FAIL: $name:ident, $func:ident, $src:expr) => {
-            #[cfg(test)]
-            #[test]
-            fn $name() {
-                let src: &str = $src;
-                let func = $func;
+    loop {
+        match tokenizer.next_token() {
+            Ok(Some(tok)) => tokens.push(tok),
+            Ok(None) => break,
+            Err(e) if tokenizer.remaining_text.is_empty() => {
+                // we hit the end of the file part way through a string
+                tokenizer.modes.clear();
+                diagnostics.push(e);
+            },
+            Err(e) => {
+                let start = tokenizer.current_index;
+                let skipped = tokenizer.recover();
+                tokens.push((TokenKind::Error, start, start + skipped));
+                diagnostics.push(e);
+            },
+        }
+    }
+
+    (tokens, diagnostics)
+}
+
+/// Turn a string of Maple code into a list of tokens like `tokenize()`, but
+/// keeping track of the whitespace and comments around each token so the
+/// original source can be reconstructed byte-for-byte.
+///
+/// A token's trailing trivia is everything after it up to (but not
+/// including) the end of the line, while everything else belongs to the
+/// following token's leading trivia. The list always ends with an `EOF` token
+/// which carries whatever is left at the end of the input.
+pub fn tokenize_lossless(src: &str) -> Result<Vec<LosslessToken>> {
+    let mut tokenizer = Tokenizer::lossless(src);
+    let mut tokens: Vec<LosslessToken> = Vec::new();
+
+    loop {
+        let next = tokenizer.next_token()?;
+        let mut leading = tokenizer.take_trivia();
+
+        if let Some(previous) = tokens.last_mut() {
+            let (trailing, rest) = split_trivia(leading);
+            previous.trailing_trivia = trailing;
+            leading = rest;
+        }
+
+        let (kind, start, end) = next.unwrap_or((TokenKind::EOF, src.len(), src.len()));
+        let is_eof = kind == TokenKind::EOF;
+
+        tokens.push(LosslessToken {
+            kind,
+            start,
+            end,
+            leading_trivia: leading,
+            trailing_trivia: Vec::new(),
+        });
+
+        if is_eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+/// The different kinds of trivia, the parts of the source code which don't
+/// affect its meaning.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TriviaKind {
+    /// A run of whitespace, not including newlines.
+    Whitespace,
+    /// A single `\n` or `\r\n`.
+    Newline,
+    /// A `// line comment`, not including the newline.
+    LineComment,
+    /// A `/* block comment */`.
+    BlockComment,
+}
+
+/// A piece of trivia and the half-open interval it covers in the source.
+pub type RawTrivia = (TriviaKind, usize, usize);
+
+/// A token and its surrounding trivia, as produced by `tokenize_lossless()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LosslessToken {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+    pub leading_trivia: Vec<RawTrivia>,
+    pub trailing_trivia: Vec<RawTrivia>,
+}
+
+/// Whitespace or a comment attached to a `Token`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Token {
+    /// The token's location relative to the rest of the files being
+    /// processed.
+    pub span: Span,
+    /// What kind of token is this?
+    pub kind: TokenKind,
+    /// Trivia immediately before this token. Only populated when lexing in
+    /// lossless mode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub leading_trivia: Vec<Trivia>,
+    /// Trivia after this token, up to the end of the line. Only populated
+    /// when lexing in lossless mode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailing_trivia: Vec<Trivia>,
+}
+
+impl Token {
+    /// Create a new token out of a `Span` and something which can be turned
+    /// into a `TokenKind`.
+    pub fn new<K: Into<TokenKind>>(span: Span, kind: K) -> Token {
+        let kind = kind.into();
+        Token {
+            span,
+            kind,
+            leading_trivia: Vec::new(),
+            trailing_trivia: Vec::new(),
+        }
+    }
+}
+
+impl<T> From<T> for Token
+    where T: Into<TokenKind> {
+    fn from(other: T) -> Token {
+        Token::new(Span::dummy(), other)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::codemap::{CodeMap, Span};
+    use crate::errors::Error;
+    use crate::lex::{skip_comments, skip_whitespace, tokenize, tokenize_identifier, tokenize_lossless, tokenize_number,
+                    tokenize_recovering, tokenize_single_token, NumberSuffix, TokenKind, TriviaKind};
+    macro_rules! lexer_test {
+        (FAIL: $name:ident, $func:ident, $src:expr) => {
+            #[cfg(test)]
+            #[test]
+            fn $name() {
+                let src: &str = $src;
+                let func = $func;
 
                 let got = func(src);
                 assert!(got.is_err(), "{:?} should be an error", got);
@@ -352,6 +1396,19 @@ mod test {
     lexer_test!(tokenize_ident_containing_an_underscore, tokenize_identifier, "Foo_bar" => "Foo_bar");
     lexer_test!(FAIL: tokenize_ident_cant_start_with_number, tokenize_identifier, "7Foo_bar");
     lexer_test!(FAIL: tokenize_ident_cant_start_with_dot, tokenize_identifier, ".Foo_bar");
+    lexer_test!(FAIL: tokenize_ident_cant_start_with_combining_mark, tokenize_identifier, "\u{301}foo");
+    lexer_test!(tokenize_a_unicode_identifier, tokenize_identifier, "größe" => "größe");
+    lexer_test!(tokenize_ident_containing_a_combining_mark, tokenize_identifier, "e\u{301}t\u{e9}" => "\u{e9}t\u{e9}");
+    lexer_test!(tokenize_ident_stops_at_a_symbol, tokenize_identifier, "foo\u{2192}bar" => "foo");
+    lexer_test!(tokenize_keyword_fun, tokenize_identifier, "fun" => TokenKind::Fun);
+    lexer_test!(tokenize_keyword_companion, tokenize_identifier, "companion" => TokenKind::Companion);
+    lexer_test!(tokenize_keyword_self, tokenize_identifier, "self" => TokenKind::SelfValue);
+    lexer_test!(tokenize_keyword_nil, tokenize_identifier, "nil" => TokenKind::Nil);
+    lexer_test!(tokenize_keyword_prefix_is_an_identifier, tokenize_identifier, "value" => "value");
+    lexer_test!(tokenize_keyword_suffix_is_an_identifier, tokenize_identifier, "funny" => "funny");
+    lexer_test!(tokenize_capitalised_keyword_is_an_identifier, tokenize_identifier, "Self" => "Self");
+    lexer_test!(tokenize_ident_starting_with_underscore, tokenize_single_token, "_unused" => "_unused");
+    lexer_test!(tokenize_lone_underscore, tokenize_single_token, "_, last" => TokenKind::Underscore);
 
     #[test]
     fn skip_past_several_whitespace_chars() {
@@ -376,7 +1433,7 @@ mod test {
             #[cfg(test)]
             #[test]
             fn $name() {
-                let got = skip_comments($src);
+                let got = skip_comments($src).unwrap();
                 assert_eq!(got, $should_be);
             }
         }
@@ -386,6 +1443,49 @@ mod test {
     comment_test!(comment_skip_self_closed, "/* Hello World */ asd" => 17);
     comment_test!(comment_skip_ignores_alphanumeric, "123 hello world" => 0);
     comment_test!(comment_skip_ignores_whitespace, "   /* */ 123 hello world" => 0);
+    comment_test!(slash_slash_at_end_of_input, "// foo bar" => 10);
+    comment_test!(comment_skip_nested, "/* a /* b */ c */ asd" => 17);
+    comment_test!(comment_skip_empty_block, "/**/ asd" => 4);
+    comment_test!(comment_skip_leaves_line_doc_comments, "/// docs\n 1234" => 0);
+    comment_test!(comment_skip_leaves_block_doc_comments, "/** docs */ 1234" => 0);
+    comment_test!(comment_skip_quadruple_slash_isnt_a_doc_comment, "//// foo\n 1234" => 9);
+
+    #[cfg(test)]
+    #[test]
+    fn unterminated_nested_comment_is_an_error() {
+        let err = skip_comments("/* a /* b */ c").unwrap_err();
+
+        match err {
+            Error::UnterminatedComment { .. } => {},
+            other => panic!("Unexpected error: {}", other),
+        }
+    }
+
+    lexer_test!(tokenize_a_line_doc_comment, tokenize_single_token, "/// Some docs\nfun" => TokenKind::DocComment(" Some docs".to_string()));
+    lexer_test!(tokenize_a_block_doc_comment, tokenize_single_token, "/** Some /* nested */ docs */" => TokenKind::DocComment(" Some /* nested */ docs ".to_string()));
+    lexer_test!(FAIL: tokenize_an_unterminated_doc_comment, tokenize_single_token, "/** Some docs");
+
+    #[cfg(test)]
+    #[test]
+    fn doc_comments_are_tokens_but_comments_arent() {
+        let src = "// not docs\n/// Docs for foo\r\n/* not /* docs */ */ foo";
+        let should_be = vec![
+            (TokenKind::DocComment(" Docs for foo".to_string()), 12, 28),
+            (TokenKind::from("foo"), 51, 54),
+        ];
+
+        let got = tokenize(src).unwrap();
+        assert_eq!(got, should_be);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn unterminated_comment_reports_where_it_started() {
+        let src = "foo /* bar /* baz */";
+
+        let err = tokenize(src).unwrap_err();
+        assert_eq!(err, Error::UnterminatedComment { span: Span::new(4, src.len()) });
+    }
 
     #[cfg(test)]
     #[test]
@@ -403,6 +1503,558 @@ mod test {
         assert_eq!(got, should_be);
     }
 
+    #[cfg(test)]
+    #[test]
+    fn tokenize_a_trait_function_declaration() {
+        let src = "trait fun to<T>(): T";
+        let should_be = vec![
+            (TokenKind::Trait, 0, 5),
+            (TokenKind::Fun, 6, 9),
+            (TokenKind::from("to"), 10, 12),
+            (TokenKind::OpenAngle, 12, 13),
+            (TokenKind::from("T"), 13, 14),
+            (TokenKind::CloseAngle, 14, 15),
+            (TokenKind::OpenParen, 15, 16),
+            (TokenKind::CloseParen, 16, 17),
+            (TokenKind::Colon, 17, 18),
+            (TokenKind::from("T"), 19, 20),
+        ];
+
+        let got = tokenize(src).unwrap();
+        assert_eq!(got, should_be);
+    }
+
+    lexer_test!(tokenize_double_equals, tokenize_single_token, "== b" => TokenKind::DoubleEquals);
+    lexer_test!(tokenize_not_equals, tokenize_single_token, "!= b" => TokenKind::NotEquals);
+    lexer_test!(tokenize_arrow, tokenize_single_token, "-> brown" => TokenKind::Arrow);
+    lexer_test!(tokenize_double_colon, tokenize_single_token, "::io" => TokenKind::DoubleColon);
+    lexer_test!(tokenize_plus_equals, tokenize_single_token, "+= 1" => TokenKind::PlusEquals);
+    lexer_test!(tokenize_minus_minus, tokenize_single_token, "--" => TokenKind::MinusMinus);
+    lexer_test!(tokenize_less_than_equals, tokenize_single_token, "<= 1" => TokenKind::LessThanEquals);
+    lexer_test!(tokenize_boolean_or, tokenize_single_token, "|| b" => TokenKind::DoublePipe);
+    lexer_test!(tokenize_throws, tokenize_single_token, "?!Paycheck" => TokenKind::Throws);
+    lexer_test!(tokenize_lone_question_mark, tokenize_single_token, "? = nil" => TokenKind::Question);
+
+    #[cfg(test)]
+    #[test]
+    fn compound_operators_use_maximal_munch() {
+        let src = "a === b";
+        let should_be = vec![
+            (TokenKind::from("a"), 0, 1),
+            (TokenKind::DoubleEquals, 2, 4),
+            (TokenKind::Equals, 4, 5),
+            (TokenKind::from("b"), 6, 7),
+        ];
+
+        let got = tokenize(src).unwrap();
+        assert_eq!(got, should_be);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn separated_operators_stay_separate() {
+        let src = "a = = b";
+        let should_be = vec![
+            (TokenKind::from("a"), 0, 1),
+            (TokenKind::Equals, 2, 3),
+            (TokenKind::Equals, 4, 5),
+            (TokenKind::from("b"), 6, 7),
+        ];
+
+        let got = tokenize(src).unwrap();
+        assert_eq!(got, should_be);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn tokenize_a_namespace_path() {
+        let src = "sys::io::STDOUT";
+        let should_be = vec![
+            (TokenKind::from("sys"), 0, 3),
+            (TokenKind::DoubleColon, 3, 5),
+            (TokenKind::from("io"), 5, 7),
+            (TokenKind::DoubleColon, 7, 9),
+            (TokenKind::from("STDOUT"), 9, 15),
+        ];
+
+        let got = tokenize(src).unwrap();
+        assert_eq!(got, should_be);
+    }
+
+    lexer_test!(tokenize_a_plain_string, tokenize_single_token, r#""brown""# => TokenKind::QuotedString("brown".to_string()));
+    lexer_test!(tokenize_an_empty_string, tokenize_single_token, r#""""# => TokenKind::QuotedString(String::new()));
+    lexer_test!(tokenize_string_escapes, tokenize_single_token, r#""a\n\t\"b\"\$c""# => TokenKind::QuotedString("a\n\t\"b\"$c".to_string()));
+    lexer_test!(tokenize_unicode_escape, tokenize_single_token, r#""\u{1F341}""# => TokenKind::QuotedString("\u{1F341}".to_string()));
+    lexer_test!(tokenize_triple_quoted_string, tokenize_single_token, "\"\"\"\n  a \"quoted\" line\n\"\"\"" => TokenKind::QuotedString("\n  a \"quoted\" line\n".to_string()));
+    lexer_test!(tokenize_start_of_interpolated_string, tokenize_single_token, r#""Hello, ${name}!""# => TokenKind::StringStart);
+    lexer_test!(FAIL: tokenize_unterminated_string, tokenize_single_token, r#""Hello"#);
+    lexer_test!(FAIL: tokenize_string_across_lines, tokenize_single_token, "\"Hello\nWorld\"");
+    lexer_test!(FAIL: tokenize_unknown_escape, tokenize_single_token, r#""\q""#);
+    lexer_test!(FAIL: tokenize_unclosed_unicode_escape, tokenize_single_token, r#""\u{1F341""#);
+    lexer_test!(FAIL: tokenize_out_of_range_unicode_escape, tokenize_single_token, r#""\u{110000}""#);
+
+    #[cfg(test)]
+    #[test]
+    fn tokenize_an_interpolated_string() {
+        let src = r#""${first} ${last}""#;
+        let should_be = vec![
+            (TokenKind::StringStart, 0, 1),
+            (TokenKind::Dollar, 1, 2),
+            (TokenKind::OpenCurly, 2, 3),
+            (TokenKind::from("first"), 3, 8),
+            (TokenKind::CloseCurly, 8, 9),
+            (TokenKind::StringFragment(" ".to_string()), 9, 10),
+            (TokenKind::Dollar, 10, 11),
+            (TokenKind::OpenCurly, 11, 12),
+            (TokenKind::from("last"), 12, 16),
+            (TokenKind::CloseCurly, 16, 17),
+            (TokenKind::StringEnd, 17, 18),
+        ];
+
+        let got = tokenize(src).unwrap();
+        assert_eq!(got, should_be);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn tokenize_interpolation_containing_blocks_and_strings() {
+        let src = r#""a${ f({ "b" }) }c""#;
+        let should_be = vec![
+            (TokenKind::StringStart, 0, 1),
+            (TokenKind::StringFragment("a".to_string()), 1, 2),
+            (TokenKind::Dollar, 2, 3),
+            (TokenKind::OpenCurly, 3, 4),
+            (TokenKind::from("f"), 5, 6),
+            (TokenKind::OpenParen, 6, 7),
+            (TokenKind::OpenCurly, 7, 8),
+            (TokenKind::QuotedString("b".to_string()), 9, 12),
+            (TokenKind::CloseCurly, 13, 14),
+            (TokenKind::CloseParen, 14, 15),
+            (TokenKind::CloseCurly, 16, 17),
+            (TokenKind::StringFragment("c".to_string()), 17, 18),
+            (TokenKind::StringEnd, 18, 19),
+        ];
+
+        let got = tokenize(src).unwrap();
+        assert_eq!(got, should_be);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn tokenize_a_simple_variable_interpolation() {
+        let src = r#""$name is $age""#;
+        let should_be = vec![
+            (TokenKind::StringStart, 0, 1),
+            (TokenKind::Dollar, 1, 2),
+            (TokenKind::from("name"), 2, 6),
+            (TokenKind::StringFragment(" is ".to_string()), 6, 10),
+            (TokenKind::Dollar, 10, 11),
+            (TokenKind::from("age"), 11, 14),
+            (TokenKind::StringEnd, 14, 15),
+        ];
+
+        let got = tokenize(src).unwrap();
+        assert_eq!(got, should_be);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn variable_interpolations_are_always_identifiers() {
+        let inputs = vec![
+            (r#""$b""#, vec![
+                TokenKind::StringStart,
+                TokenKind::Dollar,
+                TokenKind::from("b"),
+                TokenKind::StringEnd,
+            ]),
+            (r#""$b's""#, vec![
+                TokenKind::StringStart,
+                TokenKind::Dollar,
+                TokenKind::from("b"),
+                TokenKind::StringFragment("'s".to_string()),
+                TokenKind::StringEnd,
+            ]),
+        ];
+
+        for (src, should_be) in inputs {
+            let got: Vec<TokenKind> = tokenize(src).unwrap().into_iter().map(|(kind, _, _)| kind).collect();
+            assert_eq!(got, should_be, "{}", src);
+        }
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn tokenize_a_multiline_interpolated_string() {
+        let src = "\"\"\"\n// ${age} \"years\"\n\"\"\" + 1";
+        let should_be = vec![
+            (TokenKind::StringStart, 0, 3),
+            (TokenKind::StringFragment("\n// ".to_string()), 3, 7),
+            (TokenKind::Dollar, 7, 8),
+            (TokenKind::OpenCurly, 8, 9),
+            (TokenKind::from("age"), 9, 12),
+            (TokenKind::CloseCurly, 12, 13),
+            (TokenKind::StringFragment(" \"years\"\n".to_string()), 13, 22),
+            (TokenKind::StringEnd, 22, 25),
+            (TokenKind::Plus, 26, 27),
+            (TokenKind::from(1), 28, 29),
+        ];
+
+        let got = tokenize(src).unwrap();
+        assert_eq!(got, should_be);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn unterminated_interpolated_string_points_at_its_start() {
+        let src = r#"foo = "${bar}"#;
+
+        let err = tokenize(src).unwrap_err();
+        assert_eq!(err, Error::UnterminatedString { span: Span::new(6, src.len()) });
+    }
+
+    lexer_test!(tokenize_a_char, tokenize_single_token, "'a'" => TokenKind::Character('a'));
+    lexer_test!(tokenize_a_non_ascii_char, tokenize_single_token, "'é'" => TokenKind::Character('é'));
+    lexer_test!(tokenize_an_escaped_char, tokenize_single_token, r"'\n'" => TokenKind::Character('\n'));
+    lexer_test!(tokenize_an_escaped_quote_char, tokenize_single_token, r"'\''" => TokenKind::Character('\''));
+    lexer_test!(tokenize_a_unicode_escape_char, tokenize_single_token, r"'\u{1F600}'" => TokenKind::Character('\u{1F600}'));
+    lexer_test!(tokenize_a_double_quote_char, tokenize_single_token, r#"'"'"# => TokenKind::Character('"'));
+    lexer_test!(tokenize_a_byte, tokenize_single_token, "b'a'" => TokenKind::Byte(b'a'));
+    lexer_test!(tokenize_an_escaped_byte, tokenize_single_token, r"b'\t'" => TokenKind::Byte(b'\t'));
+    lexer_test!(tokenize_a_hex_byte, tokenize_single_token, r"b'\xfF'" => TokenKind::Byte(0xff));
+    lexer_test!(tokenize_a_byte_string, tokenize_single_token, r#"b"a\n\x00${b}\"""# => TokenKind::ByteString(b"a\n\x00${b}\"".to_vec()));
+    lexer_test!(tokenize_an_empty_byte_string, tokenize_single_token, r#"b"""# => TokenKind::ByteString(Vec::new()));
+    lexer_test!(tokenize_b_on_its_own_is_an_identifier, tokenize_single_token, "b + 1" => "b");
+    lexer_test!(FAIL: tokenize_an_empty_char, tokenize_single_token, "''");
+    lexer_test!(FAIL: tokenize_a_char_with_two_characters, tokenize_single_token, "'ab'");
+    lexer_test!(FAIL: tokenize_an_unterminated_char, tokenize_single_token, "'a");
+    lexer_test!(FAIL: tokenize_a_char_ending_in_a_backslash, tokenize_single_token, "'\\");
+    lexer_test!(FAIL: tokenize_a_non_ascii_byte, tokenize_single_token, "b'é'");
+    lexer_test!(FAIL: tokenize_a_non_ascii_byte_string, tokenize_single_token, r#"b"café""#);
+    lexer_test!(FAIL: tokenize_a_bad_hex_byte, tokenize_single_token, r"b'\xZZ'");
+    lexer_test!(FAIL: tokenize_a_short_hex_byte, tokenize_single_token, r"b'\x1'");
+    lexer_test!(FAIL: tokenize_an_unterminated_byte_string, tokenize_single_token, "b\"abc\ndef\"");
+
+    #[cfg(test)]
+    #[test]
+    fn bad_char_literals_point_at_the_whole_literal() {
+        let inputs = vec![
+            ("x = ''", Error::EmptyCharLiteral { span: Span::new(4, 6) }),
+            ("x = 'abc' + 1", Error::OverlongCharLiteral { span: Span::new(4, 9) }),
+            ("x = 'a\n'", Error::UnterminatedCharLiteral { span: Span::new(4, 6) }),
+            ("x = b'\\x'", Error::InvalidEscape { sequence: r"\x".to_string(), span: Span::new(6, 8) }),
+            ("x = b\"ab\u{e9}\"", Error::NonAsciiByte { ch: '\u{e9}', span: Span::new(8, 10) }),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(tokenize(src).unwrap_err(), should_be, "{}", src);
+        }
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn recovering_tokenizer_skips_broken_char_literals() {
+        let src = "a 'bc' b'é' b\"ü\" d";
+        let should_be = vec![
+            (TokenKind::from("a"), 0, 1),
+            (TokenKind::Error, 2, 6),
+            (TokenKind::Error, 7, 12),
+            (TokenKind::Error, 13, 18),
+            (TokenKind::from("d"), 19, 20),
+        ];
+
+        let (tokens, diagnostics) = tokenize_recovering(src);
+
+        assert_eq!(tokens, should_be);
+        assert_eq!(diagnostics.len(), 3);
+    }
+
+    lexer_test!(tokenize_an_integer, tokenize_number, "1234" => 1234);
+    lexer_test!(tokenize_a_decimal, tokenize_number, "12.5" => 12.5);
+    lexer_test!(tokenize_a_leading_dot_decimal, tokenize_single_token, ".05" => 0.05);
+    lexer_test!(tokenize_digit_separators, tokenize_number, "1_000_000" => 1_000_000);
+    lexer_test!(tokenize_decimal_separators, tokenize_number, "1_000.000_5" => 1_000.000_5);
+    lexer_test!(tokenize_a_hex_integer, tokenize_number, "0xFF_ff" => 0xFFFF);
+    lexer_test!(tokenize_an_octal_integer, tokenize_number, "0o755" => 0o755);
+    lexer_test!(tokenize_a_binary_integer, tokenize_number, "0b1010_0101" => 0b1010_0101);
+    lexer_test!(tokenize_an_exponent, tokenize_number, "1e3" => 1e3);
+    lexer_test!(tokenize_a_negative_exponent, tokenize_number, "2.5E-3" => 2.5e-3);
+    lexer_test!(tokenize_a_positive_exponent, tokenize_number, "2e+3" => 2e3);
+    lexer_test!(tokenize_an_integer_suffix, tokenize_number, "10u8" => TokenKind::Integer { value: 10, suffix: Some(NumberSuffix::U8) });
+    lexer_test!(tokenize_a_hex_suffix, tokenize_number, "0xffi64" => TokenKind::Integer { value: 0xff, suffix: Some(NumberSuffix::I64) });
+    lexer_test!(tokenize_a_float_suffix, tokenize_number, "1.5f32" => TokenKind::Decimal { value: 1.5, suffix: Some(NumberSuffix::F32) });
+    lexer_test!(tokenize_a_float_suffix_on_an_integer, tokenize_number, "10f64" => TokenKind::Decimal { value: 10.0, suffix: Some(NumberSuffix::F64) });
+    lexer_test!(tokenize_a_method_call_on_an_integer, tokenize_number, "1.to" => 1);
+    lexer_test!(FAIL: tokenize_binary_with_a_bad_digit, tokenize_number, "0b102");
+    lexer_test!(FAIL: tokenize_octal_with_a_bad_digit, tokenize_number, "0o78");
+    lexer_test!(FAIL: tokenize_a_prefix_without_digits, tokenize_number, "0x");
+    lexer_test!(FAIL: tokenize_an_exponent_without_digits, tokenize_number, "1e+");
+    lexer_test!(FAIL: tokenize_an_unknown_suffix, tokenize_number, "10abc");
+    lexer_test!(FAIL: tokenize_an_integer_suffix_on_a_decimal, tokenize_number, "1.5u8");
+    lexer_test!(FAIL: tokenize_a_float_suffix_on_a_hex_number, tokenize_number, "0b1f32");
+    lexer_test!(FAIL: tokenize_a_trailing_separator, tokenize_number, "1_");
+    lexer_test!(FAIL: tokenize_a_separator_after_a_prefix, tokenize_number, "0x_ff");
+    lexer_test!(FAIL: tokenize_a_separator_before_the_decimal_point, tokenize_number, "1_.5");
+
+    lexer_test!(tokenize_an_integer_wider_than_64_bits, tokenize_number, "18446744073709551616" => 18446744073709551616);
+    lexer_test!(tokenize_the_largest_integer, tokenize_number, "0xffff_ffff_ffff_ffff_ffff_ffff_ffff_ffff" => u128::MAX);
+    lexer_test!(tokenize_an_out_of_range_suffixed_integer, tokenize_number, "256u8" => TokenKind::Integer { value: 256, suffix: Some(NumberSuffix::U8) });
+    lexer_test!(FAIL: tokenize_an_integer_wider_than_128_bits, tokenize_number, "340282366920938463463374607431768211456");
+
+    #[cfg(test)]
+    #[test]
+    fn out_of_range_integers_are_an_overflow_error() {
+        let err = tokenize_number("340282366920938463463374607431768211456").unwrap_err();
+
+        match err {
+            Error::IntegerOverflow { .. } => {},
+            other => panic!("Unexpected error: {}", other),
+        }
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn invalid_digits_point_at_the_bad_digit() {
+        let err = tokenize_number("0b1_0121").unwrap_err();
+
+        assert_eq!(err, Error::InvalidDigit { ch: '2', radix: 2, span: Span::new(6, 7) });
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn misplaced_separators_point_at_the_separator() {
+        let inputs = vec![
+            ("x = 1_", Span::new(5, 6)),
+            ("x = 0x_ff", Span::new(6, 7)),
+            ("x = 1.5_e3", Span::new(7, 8)),
+        ];
+
+        for (src, span) in inputs {
+            let err = tokenize(src).unwrap_err();
+            assert_eq!(err, Error::MisplacedSeparator { span }, "{}", src);
+        }
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn leading_dots_only_start_decimals_where_a_value_can_start() {
+        let inputs = vec![
+            ("x = .05", vec![TokenKind::from("x"), TokenKind::Equals, TokenKind::from(0.05)]),
+            ("f(.5)", vec![TokenKind::from("f"), TokenKind::OpenParen, TokenKind::from(0.5), TokenKind::CloseParen]),
+            ("pair.0", vec![TokenKind::from("pair"), TokenKind::Dot, TokenKind::from(0)]),
+            ("f().1", vec![TokenKind::from("f"), TokenKind::OpenParen, TokenKind::CloseParen, TokenKind::Dot, TokenKind::from(1)]),
+            ("1..5", vec![TokenKind::from(1), TokenKind::Dot, TokenKind::Dot, TokenKind::from(5)]),
+        ];
+
+        for (src, should_be) in inputs {
+            let got: Vec<TokenKind> = tokenize(src).unwrap().into_iter().map(|(kind, _, _)| kind).collect();
+            assert_eq!(got, should_be, "{}", src);
+        }
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn lossless_tokens_carry_their_trivia() {
+        let src = "foo // bar\n  /* baz */ 1\n";
+
+        let got = tokenize_lossless(src).unwrap();
+
+        assert_eq!(got.len(), 3);
+        assert_eq!(got[0].kind, TokenKind::from("foo"));
+        assert!(got[0].leading_trivia.is_empty());
+        assert_eq!(got[0].trailing_trivia, vec![
+            (TriviaKind::Whitespace, 3, 4),
+            (TriviaKind::LineComment, 4, 10),
+        ]);
+        assert_eq!(got[1].kind, TokenKind::from(1));
+        assert_eq!(got[1].leading_trivia, vec![
+            (TriviaKind::Newline, 10, 11),
+            (TriviaKind::Whitespace, 11, 13),
+            (TriviaKind::BlockComment, 13, 22),
+            (TriviaKind::Whitespace, 22, 23),
+        ]);
+        assert!(got[1].trailing_trivia.is_empty());
+        assert_eq!(got[2].kind, TokenKind::EOF);
+        assert_eq!(got[2].leading_trivia, vec![(TriviaKind::Newline, 24, 25)]);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn crlf_is_a_single_newline() {
+        let src = "a\r\n\tb";
+
+        let got = tokenize_lossless(src).unwrap();
+
+        assert_eq!(got[1].leading_trivia, vec![
+            (TriviaKind::Newline, 1, 3),
+            (TriviaKind::Whitespace, 3, 4),
+        ]);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn lossless_tokens_round_trip_the_prototype_example() {
+        let src = include_str!("../../../examples/maple-code/prototypes/proto_2023-12-22.maple");
+
+        let mut reconstructed = String::new();
+        for token in tokenize_lossless(src).unwrap() {
+            for &(_, start, end) in &token.leading_trivia {
+                reconstructed.push_str(&src[start..end]);
+            }
+            reconstructed.push_str(&src[token.start..token.end]);
+            for &(_, start, end) in &token.trailing_trivia {
+                reconstructed.push_str(&src[start..end]);
+            }
+        }
+
+        assert_eq!(reconstructed, src);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn recovering_tokenizer_reports_every_bad_character() {
+        let src = "a ` b ` c ` d ` e `";
+
+        let (tokens, diagnostics) = tokenize_recovering(src);
+
+        let kinds: Vec<_> = tokens.iter().map(|(kind, _, _)| kind.clone()).collect();
+        assert_eq!(kinds, vec![
+            TokenKind::from("a"), TokenKind::Error,
+            TokenKind::from("b"), TokenKind::Error,
+            TokenKind::from("c"), TokenKind::Error,
+            TokenKind::from("d"), TokenKind::Error,
+            TokenKind::from("e"), TokenKind::Error,
+        ]);
+
+        let locations: Vec<_> = diagnostics.iter().map(Error::span).collect();
+        assert_eq!(locations, vec![
+            Span::new(2, 3), Span::new(6, 7), Span::new(10, 11), Span::new(14, 15), Span::new(18, 19),
+        ]);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn recovering_tokenizer_skips_whole_broken_tokens() {
+        let src = "foo `%^& 0b102 \"bad \\q \\\"escape\" bar";
+        let should_be = vec![
+            (TokenKind::from("foo"), 0, 3),
+            (TokenKind::Error, 4, 7),
+            (TokenKind::Ampersand, 7, 8),
+            (TokenKind::Error, 9, 14),
+            (TokenKind::Error, 15, 32),
+            (TokenKind::from("bar"), 33, 36),
+        ];
+
+        let (tokens, diagnostics) = tokenize_recovering(src);
+
+        assert_eq!(tokens, should_be);
+        assert_eq!(diagnostics.len(), 3);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn recovering_tokenizer_handles_broken_interpolated_strings() {
+        let src = "\"${a} \\q ${b}\" c";
+        let should_be = vec![
+            (TokenKind::StringStart, 0, 1),
+            (TokenKind::Dollar, 1, 2),
+            (TokenKind::OpenCurly, 2, 3),
+            (TokenKind::from("a"), 3, 4),
+            (TokenKind::CloseCurly, 4, 5),
+            (TokenKind::Error, 5, 14),
+            (TokenKind::from("c"), 15, 16),
+        ];
+
+        let (tokens, diagnostics) = tokenize_recovering(src);
+
+        assert_eq!(tokens, should_be);
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn recovering_tokenizer_skips_the_newline_ending_a_broken_interpolated_string() {
+        let inputs = vec![
+            ("x = \"abc${a}\n", (12, 13)),
+            ("\"${a}\nb\"", (5, 6)),
+        ];
+
+        for (src, error) in inputs {
+            let (tokens, diagnostics) = tokenize_recovering(src);
+
+            let errors: Vec<_> = tokens.iter()
+                .filter(|(kind, _, _)| *kind == TokenKind::Error)
+                .map(|&(_, start, end)| (start, end))
+                .collect();
+            assert_eq!(errors[0], error, "{:?}", src);
+            assert!(errors.iter().all(|(start, end)| start < end), "{:?}: {:?}", src, tokens);
+            assert!(!diagnostics.is_empty());
+        }
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn recovering_tokenizer_reports_unterminated_strings() {
+        let src = "a \"${b}";
+
+        let (tokens, diagnostics) = tokenize_recovering(src);
+
+        assert_eq!(tokens.len(), 6);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span(), Span::new(2, 7));
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn recovering_tokenizer_matches_tokenize_on_valid_input() {
+        let src = include_str!("../../../examples/maple-code/prototypes/proto_2023-12-22.maple");
+
+        let (tokens, diagnostics) = tokenize_recovering(src);
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(tokens, tokenize(src).unwrap());
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn token_stream_matches_tokenize() {
+        let src = include_str!("../../../examples/maple-code/prototypes/proto_2023-12-22.maple");
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("proto.maple", src);
+
+        let streamed: Vec<_> = fm.tokens().map(|tok| tok.unwrap()).collect();
+        let should_be = fm.register_tokens(tokenize(src).unwrap());
+
+        assert_eq!(streamed, should_be);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn token_stream_can_look_ahead() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "val x = 1");
+        let mut tokens = fm.tokens();
+
+        assert_eq!(tokens.peek_nth(2).unwrap().as_ref().unwrap().kind, TokenKind::Equals);
+        assert_eq!(tokens.peek().unwrap().as_ref().unwrap().kind, TokenKind::Val);
+        assert!(tokens.peek_nth(4).is_none());
+
+        assert_eq!(tokens.next().unwrap().unwrap().kind, TokenKind::Val);
+        assert_eq!(tokens.peek().unwrap().as_ref().unwrap().kind, TokenKind::from("x"));
+        assert_eq!(tokens.count(), 3);
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn token_stream_stops_after_an_error() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "a ` b");
+        let mut tokens = fm.tokens();
+
+        assert!(tokens.next().unwrap().is_ok());
+        assert!(tokens.next().unwrap().is_err());
+        assert!(tokens.next().is_none());
+    }
+
     #[cfg(test)]
     #[test]
     fn tokenizer_detects_invalid_stuff() {
@@ -410,10 +2062,47 @@ mod test {
         let index_of_backtick = 8;
 
         let err = tokenize(src).unwrap_err();
-        match err.kind() {
-            &ErrorKind::MessageWithLocation(loc, _) => assert_eq!(loc, index_of_backtick),
-            other => panic!("Unexpected error: {}", other),
+        let span = Span::new(index_of_backtick, index_of_backtick + 1);
+        assert_eq!(err, Error::UnknownCharacter { ch: '`', span });
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn invalid_escapes_point_at_the_escape() {
+        let inputs = vec![
+            (r#"val s = "ab\qc""#, r"\q", Span::new(11, 13)),
+            (r#""\u{110000}""#, r"\u{110000}", Span::new(1, 11)),
+            (r#""${a} \u""#, r"\u", Span::new(6, 8)),
+        ];
+
+        for (src, sequence, span) in inputs {
+            let err = tokenize(src).unwrap_err();
+            assert_eq!(err, Error::InvalidEscape { sequence: sequence.to_string(), span }, "{}", src);
         }
     }
+
+    #[cfg(test)]
+    #[test]
+    fn unclosed_unicode_escapes_stop_at_the_hex_digits() {
+        let inputs = vec![
+            ("val s = \"\\u{1F341\"\nval t = \"}\"", Span::new(9, 17)),
+            ("\"\\u{41 \"", Span::new(1, 6)),
+            ("\"\\u{", Span::new(1, 4)),
+        ];
+
+        for (src, span) in inputs {
+            let err = tokenize(src).unwrap_err();
+            assert_eq!(err, Error::UnterminatedEscape { span }, "{}", src);
+        }
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn unterminated_strings_run_to_the_end_of_the_line() {
+        let src = "a = \"abc\nb";
+
+        let err = tokenize(src).unwrap_err();
+        assert_eq!(err, Error::UnterminatedString { span: Span::new(4, 8) });
+    }
 }
 
@@ -1,3 +1,49 @@
+//! The lexer for the Maple language.
+//!
+//! Most users will want to load their source code into a `CodeMap` and lex it
+//! with `lex_file()` or `FileMap::tokenize()`, which give back `Token`s whose
+//! spans can be looked up in that `CodeMap`. The raw functions (`tokenize()`,
+//! `tokenize_recovering()` and `tokenize_lossless()`) work on plain strings
+//! instead.
+//!
+//! ```rust
+//! use maple_lexer::{CodeMap, TokenKind};
+//!
+//! let mut map = CodeMap::new();
+//! let file = map.insert_file("main.maple", "val answer = 42");
+//! let tokens = file.tokenize().unwrap();
+//!
+//! assert_eq!(tokens[0].kind, TokenKind::Val);
+//! assert_eq!(map.lookup(tokens[1].span), "answer");
+//! ```
+
+use std::path::Path;
+
 mod lex;
 mod errors;
-mod codemap;
\ No newline at end of file
+mod codemap;
+mod diagnostics;
+mod json;
+mod confusables;
+mod incremental;
+
+pub use crate::codemap::{CodeMap, ColumnEncoding, FileMap, LineColumn, Location, Span};
+pub use crate::confusables::check_identifiers;
+pub use crate::diagnostics::{Diagnostic, Label, Renderer, Severity, Suggestion};
+pub use crate::errors::{Error, FileError, LoadError, Result};
+pub use crate::incremental::{relex, Edit, Relexed};
+pub use crate::json::{to_json, JsonDiagnostic, JsonEmitter, JsonSpan, JsonSuggestion};
+pub use crate::lex::{tokenize, tokenize_lossless, tokenize_recovering, LosslessToken, NumberSuffix,
+                     RawTrivia, Token, TokenKind, Tokens, Trivia, TriviaKind};
+
+/// Load a file from disk into the `CodeMap` and turn its contents into
+/// tokens.
+///
+/// The tokens (and any lexing error) have spans which can be looked up in
+/// `codemap`.
+pub fn lex_file<P: AsRef<Path>>(codemap: &mut CodeMap, path: P) -> ::std::result::Result<Vec<Token>, FileError> {
+    let file = codemap.load_file(path)?;
+    let tokens = file.tokenize()?;
+
+    Ok(tokens)
+}
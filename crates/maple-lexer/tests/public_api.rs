@@ -0,0 +1,94 @@
+//! Exercise the lexer the way other crates will, using only what's exported
+//! from the crate root.
+
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+use maple_lexer::{lex_file, tokenize, tokenize_recovering, CodeMap, Diagnostic, Error, FileError, LoadError,
+                  Renderer, Span, Token, TokenKind};
+
+fn prototype() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../../examples/maple-code/prototypes/proto_2023-12-22.maple")
+}
+
+/// Write `contents` to a file called `name` in a new temporary directory,
+/// which is deleted when the returned `TempDir` is dropped.
+fn temp_file(name: &str, contents: &str) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(name);
+    fs::write(&path, contents).unwrap();
+    (dir, path)
+}
+
+#[test]
+fn lex_a_file_from_disk() {
+    let mut map = CodeMap::new();
+
+    let tokens: Vec<Token> = lex_file(&mut map, prototype()).unwrap();
+
+    assert!(!tokens.is_empty());
+    assert_eq!(map.files().len(), 1);
+    let first: Span = tokens[0].span;
+    assert_eq!(map.lookup(first), "trait");
+    assert_eq!(tokens[0].kind, TokenKind::Trait);
+}
+
+#[test]
+fn file_and_raw_tokens_agree() {
+    let src = fs::read_to_string(prototype()).unwrap();
+    let mut map = CodeMap::new();
+    let file = map.insert_file("proto.maple", src.as_str());
+
+    let raw = tokenize(&src).unwrap();
+    let tokens = file.tokenize().unwrap();
+
+    assert_eq!(raw.len(), tokens.len());
+    for ((kind, start, end), token) in raw.iter().zip(&tokens) {
+        assert_eq!(*kind, token.kind);
+        assert_eq!(&src[*start..*end], map.lookup(token.span));
+    }
+}
+
+#[test]
+fn missing_files_are_load_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("missing.maple");
+    let mut map = CodeMap::new();
+
+    match lex_file(&mut map, &path) {
+        Err(FileError::Load(LoadError::Io { path: got, .. })) => assert_eq!(got, path),
+        other => panic!("Expected an IO error, got {:?}", other),
+    }
+}
+
+#[test]
+fn lexing_errors_point_into_the_codemap() {
+    let (_dir, path) = temp_file("bad.maple", "val x = 1\nval y = `oops`\n");
+    let mut map = CodeMap::new();
+
+    let err = match lex_file(&mut map, &path) {
+        Err(FileError::Lex(err)) => err,
+        other => panic!("Expected a lexing error, got {:?}", other),
+    };
+
+    assert!(matches!(err, Error::UnknownCharacter { ch: '`', .. }), "{}", err);
+    assert_eq!(map.lookup(err.span()), "`");
+
+    let diagnostic = Diagnostic::from(&err);
+    assert_eq!(diagnostic.code.as_deref(), Some("E0002"));
+}
+
+#[test]
+fn recovered_errors_can_be_rendered() {
+    let src = "val x = `oops`";
+    let mut map = CodeMap::new();
+    let file = map.insert_file("bad.maple", src);
+
+    let (tokens, errors) = tokenize_recovering(src);
+    let errors: Vec<_> = errors.into_iter().map(|e| file.register_error(e)).collect();
+
+    assert!(tokens.iter().any(|(kind, _, _)| *kind == TokenKind::Error));
+    let rendered = Renderer::new(&map).render(&errors[0].to_diagnostic());
+    assert!(rendered.contains("bad.maple:1:9"), "{}", rendered);
+}
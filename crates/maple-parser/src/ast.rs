@@ -0,0 +1,382 @@
+//! The abstract syntax tree produced by the parser.
+//!
+//! Every node carries the `Span` of the source code it was parsed from, which
+//! can be looked up in the `CodeMap` the file belongs to.
+
+use maple_lexer::{NumberSuffix, Span};
+use crate::errors::Declaration;
+
+/// A whole source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct File {
+    pub items: Vec<Item>,
+    pub span: Span,
+}
+
+/// A name, and where it was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A declaration, either at the top level of a file or as a member of some
+/// other declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    /// The text of any doc comments written directly before the item.
+    pub doc: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub kind: ItemKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Visibility {
+    Public,
+    Private,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItemKind {
+    Function(Box<Function>),
+    Property(Box<Property>),
+    Tuple(TupleDecl),
+    Enum(EnumDecl),
+    Object(ObjectDecl),
+    Companion(Companion),
+}
+
+/// A `fun` or `op` declaration, e.g. `|self| impl fun to<String>() = ...`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub receiver: Option<Receiver>,
+    /// Declared with `trait fun`, so implementations are expected elsewhere.
+    pub is_trait: bool,
+    /// Declared with `impl`, implementing a trait function or operator.
+    pub is_impl: bool,
+    /// Declared with `op` rather than `fun`.
+    pub is_operator: bool,
+    /// The word in brackets after `fun`, e.g. `get` in `fun(get)`.
+    pub modifier: Option<Ident>,
+    pub name: Ident,
+    pub generics: Vec<Ident>,
+    /// `None` for getters, which are declared without a parameter list.
+    pub params: Option<Vec<Param>>,
+    pub return_type: Option<Type>,
+    /// The error type from a `?!Type` clause.
+    pub throws: Option<Type>,
+    pub body: Option<FunctionBody>,
+}
+
+/// The `|...|` before a member function, saying how the value it's called
+/// on is made available to the function body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Receiver {
+    pub kind: ReceiverKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReceiverKind {
+    /// `|(first, _)|`, destructuring a tuple by position. Elements written
+    /// as `_` are `None`.
+    Tuple(Vec<Option<Ident>>),
+    /// `|{name, age}|`, picking out some of an object's fields.
+    Fields(Vec<Ident>),
+    /// `|self|`, or `|self as lhs|` to give it another name.
+    SelfValue { alias: Option<Ident> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: Ident,
+    pub ty: Type,
+    pub default: Option<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionBody {
+    /// `= expr`
+    Expr(Expr),
+    /// `{ ... }`
+    Block(Block),
+}
+
+/// A `val` declaration, either as a member or as a statement in a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    /// The word in brackets after `val`, e.g. `static` in `val(static)`.
+    pub modifier: Option<Ident>,
+    pub name: Ident,
+    pub ty: Option<Type>,
+    pub value: Option<Expr>,
+}
+
+/// `tuple Name (String, String) { ... }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TupleDecl {
+    pub name: Ident,
+    pub fields: Vec<Type>,
+    pub members: Vec<Item>,
+}
+
+/// `enum Name [A, B, C] { ... }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumDecl {
+    pub name: Ident,
+    pub variants: Vec<Ident>,
+    pub members: Vec<Item>,
+}
+
+/// `object Name { ... }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectDecl {
+    pub name: Ident,
+    pub members: Vec<Item>,
+}
+
+/// A `companion { ... }` block, holding types related to its parent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Companion {
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Type {
+    pub kind: TypeKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeKind {
+    /// A (possibly namespaced) type name with optional generic arguments,
+    /// e.g. `String` or `sys::io::Buffer<T>`.
+    Named { path: Path, args: Vec<Type> },
+    /// A type from another type's companion block, e.g. `Paycheck#Error`.
+    Companion { parent: Box<Type>, name: Ident },
+    /// `(String, String)`
+    Tuple(Vec<Type>),
+    /// `Paycheck[]`
+    Array(Box<Type>),
+    /// `Paycheck?`
+    Nullable(Box<Type>),
+}
+
+/// Names separated by `::`, e.g. `sys::io::STDOUT`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub segments: Vec<Ident>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Val { property: Property, span: Span },
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Literal(Literal),
+    /// A string with interpolation in it.
+    String(Vec<StringPart>),
+    Path(Path),
+    SelfValue,
+    /// `(a, b)`
+    Tuple(Vec<Expr>),
+    Unary { op: UnaryOp, operand: Box<Expr> },
+    Binary { op: BinaryOp, lhs: Box<Expr>, rhs: Box<Expr> },
+    Postfix { op: PostfixOp, operand: Box<Expr> },
+    /// `target = value`, or a compound assignment like `target += value`.
+    Assign { op: Option<BinaryOp>, target: Box<Expr>, value: Box<Expr> },
+    Call { callee: Box<Expr>, args: Vec<Argument> },
+    /// `object[index]`
+    Index { object: Box<Expr>, index: Box<Expr> },
+    /// `object.name`
+    Member { object: Box<Expr>, name: Ident },
+    /// `parent#Name`
+    Companion { parent: Box<Expr>, name: Ident },
+    /// `Person { name, age: 42 }`
+    Construct { ty: Path, fields: Vec<FieldInit> },
+    /// `(a, b) -> a + b`
+    Lambda { params: Vec<Ident>, body: Box<Expr> },
+    If { condition: Box<Expr>, then: Block, otherwise: Option<Box<Expr>> },
+    When { subject: Box<Expr>, arms: Vec<WhenArm> },
+    Block(Block),
+    Throw(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Integer { value: u128, suffix: Option<NumberSuffix> },
+    Decimal { value: f64, suffix: Option<NumberSuffix> },
+    String(String),
+    Character(char),
+    Byte(u8),
+    ByteString(Vec<u8>),
+    Bool(bool),
+    Nil,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Text(String),
+    Interpolation(Expr),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnaryOp {
+    /// `-x`
+    Negate,
+    /// `!x`
+    Not,
+}
+
+/// Operators written after their operand, for dealing with nullable values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PostfixOp {
+    /// `x?`, giving back `nil` from the current function if `x` is `nil`.
+    Propagate,
+    /// `x!`, which fails at runtime if `x` is `nil`.
+    AssertNotNil,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitAnd,
+    BitOr,
+    And,
+    Or,
+}
+
+/// An argument to a function call, which may be named (`init = nil`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Argument {
+    pub name: Option<Ident>,
+    pub value: Expr,
+}
+
+/// A field in a `Construct` expression. The value can be left out when it's
+/// a variable with the same name as the field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldInit {
+    pub name: Ident,
+    pub value: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhenArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternKind {
+    /// `_`
+    Wildcard,
+    /// A name, which either binds a new variable or refers to an existing
+    /// value (e.g. an enum variant) depending on what's in scope.
+    Ident(Ident),
+    Literal(Literal),
+    /// `(first, _)`
+    Tuple(Vec<Pattern>),
+}
+
+impl BinaryOp {
+    /// How this operator is written in source code.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+}
+
+impl Receiver {
+    /// The names this receiver introduces, in the order they're written.
+    pub fn bindings(&self) -> Vec<&Ident> {
+        match &self.kind {
+            ReceiverKind::Tuple(elements) => elements.iter().flatten().collect(),
+            ReceiverKind::Fields(fields) => fields.iter().collect(),
+            ReceiverKind::SelfValue { alias } => alias.iter().collect(),
+        }
+    }
+}
+
+impl ItemKind {
+    /// What sort of declaration this is, for use in error messages.
+    pub fn declaration(&self) -> Declaration {
+        match self {
+            ItemKind::Function(function) if function.is_operator => Declaration::Operator,
+            ItemKind::Function(_) => Declaration::Function,
+            ItemKind::Property(_) => Declaration::Property,
+            ItemKind::Tuple(_) => Declaration::Tuple,
+            ItemKind::Enum(_) => Declaration::Enum,
+            ItemKind::Object(_) => Declaration::Object,
+            ItemKind::Companion(_) => Declaration::Companion,
+        }
+    }
+}
+
+impl PostfixOp {
+    /// How this operator is written in source code.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            PostfixOp::Propagate => "?",
+            PostfixOp::AssertNotNil => "!",
+        }
+    }
+}
+
+impl UnaryOp {
+    /// How this operator is written in source code.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Not => "!",
+        }
+    }
+}
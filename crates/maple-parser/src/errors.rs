@@ -0,0 +1,277 @@
+//! The errors which can happen while parsing.
+
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+use maple_lexer::{Diagnostic, Span, TokenKind};
+
+/// Something went wrong while parsing, and where it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The source code couldn't be turned into tokens.
+    Lex(maple_lexer::Error),
+    /// Found a token which isn't allowed here. `expected` describes what
+    /// would have been, e.g. "an expression".
+    UnexpectedToken { found: TokenKind, expected: String, span: Span },
+    /// The file ended in the middle of something. The span is empty and
+    /// points at the end of the file.
+    UnexpectedEOF { expected: String, span: Span },
+    /// The left hand side of an assignment (e.g. `a + b = c`) isn't a
+    /// variable, property or index.
+    InvalidAssignmentTarget { span: Span },
+    /// A declaration was written somewhere it isn't allowed, e.g. a function
+    /// inside a `companion` block.
+    MisplacedItem { item: Declaration, place: Place, span: Span },
+    /// A `val` with neither a type nor a value, so there's no way to know
+    /// what its type is.
+    UntypedProperty { name: String, span: Span },
+    /// A receiver which binds the same name more than once, like
+    /// `|(a, a)|`. `first` is where the name was first bound.
+    DuplicateBinding { name: String, first: Span, span: Span },
+    /// A receiver which doesn't bind any names, like `|()|` or `|(_, _)|`.
+    EmptyReceiver { span: Span },
+    /// A function with a receiver outside of a tuple, enum or object, where
+    /// there's nothing for it to be called on.
+    TopLevelReceiver { span: Span },
+}
+
+/// What sort of declaration an `Error::MisplacedItem` is about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Declaration {
+    Function,
+    Operator,
+    Property,
+    Tuple,
+    Enum,
+    Object,
+    Companion,
+}
+
+/// Where a misplaced declaration was written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Place {
+    TopLevel,
+    Companion,
+}
+
+/// A specialised `Result` for parsing.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl Error {
+    /// The part of the source code this error applies to.
+    pub fn span(&self) -> Span {
+        match *self {
+            Error::Lex(ref inner) => inner.span(),
+            Error::UnexpectedToken { span, .. }
+            | Error::UnexpectedEOF { span, .. }
+            | Error::InvalidAssignmentTarget { span }
+            | Error::MisplacedItem { span, .. }
+            | Error::UntypedProperty { span, .. }
+            | Error::DuplicateBinding { span, .. }
+            | Error::EmptyReceiver { span }
+            | Error::TopLevelReceiver { span } => span,
+        }
+    }
+
+    /// The unique code used to identify this kind of error in diagnostics.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Error::Lex(ref inner) => inner.code(),
+            Error::UnexpectedToken { .. } => "E0100",
+            Error::UnexpectedEOF { .. } => "E0101",
+            Error::InvalidAssignmentTarget { .. } => "E0102",
+            Error::MisplacedItem { .. } => "E0103",
+            Error::UntypedProperty { .. } => "E0104",
+            Error::DuplicateBinding { .. } => "E0105",
+            Error::EmptyReceiver { .. } => "E0106",
+            Error::TopLevelReceiver { .. } => "E0107",
+        }
+    }
+
+    /// Turn this error into a `Diagnostic` which can be shown to the user.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        if let Error::Lex(inner) = self {
+            return inner.to_diagnostic();
+        }
+
+        let diagnostic = Diagnostic::error(self.to_string(), self.span())
+            .with_code(self.code());
+
+        match self {
+            Error::UnexpectedToken { expected, .. } | Error::UnexpectedEOF { expected, .. } => {
+                diagnostic.with_primary_message(format!("expected {}", expected))
+            },
+            Error::InvalidAssignmentTarget { .. } => diagnostic
+                .with_primary_message("this isn't a variable, property or index"),
+            Error::MisplacedItem { place: Place::Companion, .. } => diagnostic
+                .with_note("companion blocks can only contain types (tuples, enums and objects)"),
+            Error::MisplacedItem { item: Declaration::Companion, .. } => diagnostic
+                .with_note("companion blocks belong inside a tuple, enum or object"),
+            Error::MisplacedItem { .. } => diagnostic,
+            Error::UntypedProperty { name, .. } => diagnostic
+                .with_help(format!("give it a type (e.g. `val {}: Int`) or a value", name)),
+            Error::DuplicateBinding { name, first, .. } => diagnostic
+                .with_primary_message(format!("`{}` bound again here", name))
+                .with_label(*first, "first bound here"),
+            Error::EmptyReceiver { .. } => diagnostic
+                .with_help("use `|self|` to get the whole value, or remove the receiver"),
+            Error::TopLevelReceiver { .. } => diagnostic
+                .with_note("receivers bind the value a member function is called on, so they can only \
+                            be used inside a tuple, enum or object"),
+            Error::Lex(_) => unreachable!(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Lex(inner) => Display::fmt(inner, f),
+            Error::UnexpectedToken { found, expected, .. } => {
+                write!(f, "Expected {}, found {}", expected, describe(found))
+            },
+            Error::UnexpectedEOF { expected, .. } => {
+                write!(f, "Expected {}, found the end of the file", expected)
+            },
+            Error::InvalidAssignmentTarget { .. } => write!(f, "Invalid left hand side of assignment"),
+            Error::MisplacedItem { item, place, .. } => write!(f, "Can't declare {} {}", item, place),
+            Error::UntypedProperty { name, .. } => {
+                write!(f, "Property `{}` needs either a type or a value", name)
+            },
+            Error::DuplicateBinding { name, .. } => {
+                write!(f, "`{}` is bound more than once in the same receiver", name)
+            },
+            Error::EmptyReceiver { .. } => write!(f, "Receiver doesn't bind anything"),
+            Error::TopLevelReceiver { .. } => write!(f, "Top level functions can't have a receiver"),
+        }
+    }
+}
+
+impl Display for Declaration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Declaration::Function => "a function",
+            Declaration::Operator => "an operator",
+            Declaration::Property => "a property",
+            Declaration::Tuple => "a tuple",
+            Declaration::Enum => "an enum",
+            Declaration::Object => "an object",
+            Declaration::Companion => "a companion block",
+        };
+
+        f.write_str(description)
+    }
+}
+
+impl Display for Place {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Place::TopLevel => f.write_str("at the top level"),
+            Place::Companion => f.write_str("in a companion block"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Lex(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<maple_lexer::Error> for Error {
+    fn from(other: maple_lexer::Error) -> Error {
+        Error::Lex(other)
+    }
+}
+
+impl From<Error> for Diagnostic {
+    fn from(other: Error) -> Diagnostic {
+        other.to_diagnostic()
+    }
+}
+
+impl<'a> From<&'a Error> for Diagnostic {
+    fn from(other: &'a Error) -> Diagnostic {
+        other.to_diagnostic()
+    }
+}
+
+/// A short, human-readable description of a token for error messages.
+pub(crate) fn describe(kind: &TokenKind) -> String {
+    let symbol = match kind {
+        TokenKind::Integer { .. } | TokenKind::Decimal { .. } => return "a number".to_string(),
+        TokenKind::QuotedString(_) | TokenKind::StringStart | TokenKind::ByteString(_) => {
+            return "a string".to_string();
+        },
+        TokenKind::StringFragment(_) | TokenKind::StringEnd => return "the rest of a string".to_string(),
+        TokenKind::Character(_) | TokenKind::Byte(_) => return "a character literal".to_string(),
+        TokenKind::Identifier(name) => return format!("identifier `{}`", name),
+        TokenKind::DocComment(_) => return "a doc comment".to_string(),
+        TokenKind::Error => return "invalid input".to_string(),
+        TokenKind::EOF => return "the end of the file".to_string(),
+        TokenKind::Trait => "trait",
+        TokenKind::Fun => "fun",
+        TokenKind::Tuple => "tuple",
+        TokenKind::Enum => "enum",
+        TokenKind::Object => "object",
+        TokenKind::Impl => "impl",
+        TokenKind::Op => "op",
+        TokenKind::Val => "val",
+        TokenKind::Public => "public",
+        TokenKind::Private => "private",
+        TokenKind::When => "when",
+        TokenKind::If => "if",
+        TokenKind::Else => "else",
+        TokenKind::Throw => "throw",
+        TokenKind::Companion => "companion",
+        TokenKind::As => "as",
+        TokenKind::SelfValue => "self",
+        TokenKind::Nil => "nil",
+        TokenKind::True => "true",
+        TokenKind::False => "false",
+        TokenKind::Dollar => "$",
+        TokenKind::Hash => "#",
+        TokenKind::Colon => ":",
+        TokenKind::DoubleColon => "::",
+        TokenKind::Underscore => "_",
+        TokenKind::Dot => ".",
+        TokenKind::Comma => ",",
+        TokenKind::Question => "?",
+        TokenKind::Exclamation => "!",
+        TokenKind::Throws => "?!",
+        TokenKind::OpenParen => "(",
+        TokenKind::CloseParen => ")",
+        TokenKind::OpenSquare => "[",
+        TokenKind::CloseSquare => "]",
+        TokenKind::OpenCurly => "{",
+        TokenKind::CloseCurly => "}",
+        TokenKind::OpenAngle => "<",
+        TokenKind::CloseAngle => ">",
+        TokenKind::Plus => "+",
+        TokenKind::PlusPlus => "++",
+        TokenKind::PlusEquals => "+=",
+        TokenKind::Minus => "-",
+        TokenKind::MinusMinus => "--",
+        TokenKind::MinusEquals => "-=",
+        TokenKind::Arrow => "->",
+        TokenKind::Slash => "/",
+        TokenKind::Asterisk => "*",
+        TokenKind::Pipe => "|",
+        TokenKind::DoublePipe => "||",
+        TokenKind::Ampersand => "&",
+        TokenKind::DoubleAmpersand => "&&",
+        TokenKind::Equals => "=",
+        TokenKind::DoubleEquals => "==",
+        TokenKind::NotEquals => "!=",
+        TokenKind::LessThanEquals => "<=",
+        TokenKind::GreaterThanEquals => ">=",
+    };
+
+    if kind.is_keyword() {
+        format!("keyword `{}`", symbol)
+    } else {
+        format!("`{}`", symbol)
+    }
+}
@@ -0,0 +1,574 @@
+//! Parsing expressions, using precedence climbing.
+//!
+//! Operators are listed here from loosest to tightest binding:
+//!
+//! | Level          | Operators                            | Associativity |
+//! |----------------|--------------------------------------|---------------|
+//! | Assignment     | `=` `+=` `-=`                        | right         |
+//! | Or             | `\|\|`                               | left          |
+//! | And            | `&&`                                 | left          |
+//! | Equality       | `==` `!=`                            | left          |
+//! | Comparison     | `<` `<=` `>` `>=`                    | left          |
+//! | BitOr          | `\|`                                 | left          |
+//! | BitAnd         | `&`                                  | left          |
+//! | Additive       | `+` `-`                              | left          |
+//! | Multiplicative | `*` `/`                              | left          |
+//! | Prefix         | `-x` `!x`                            | right         |
+//! | Postfix        | `f(x)` `a[i]` `a.b` `A#B` `x?` `x!`  | left          |
+//!
+//! Paths like `sys::io::STDOUT` are read as a single name, so `::` binds
+//! tighter than anything in the table.
+//!
+//! Like comparisons in Rust, bitwise operators bind tighter than `==` so
+//! `flags & MASK == 0` is `(flags & MASK) == 0`.
+//!
+//! An infix operator has to be on the same line as its left hand side,
+//! otherwise it's the start of something new. The same goes for the
+//! brackets of a call or index, and everything else in the postfix row
+//! apart from `.`, which is allowed at the start of a line so long method
+//! chains can be split up.
+//!
+//! The lexer reads `--` and `?!` as single tokens, so they're split back up
+//! here. Before an operand `--a` is `-(-a)`, after one `a--b` is `a - (-b)`,
+//! and `x?!` is `(x?)!`.
+
+use maple_lexer::TokenKind;
+use crate::ast::*;
+use crate::errors::*;
+use crate::parser::Parser;
+
+/// How tightly an operator binds to its operands. See the module docs for
+/// which operators are at each level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Lowest,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    BitOr,
+    BitAnd,
+    Additive,
+    Multiplicative,
+    Prefix,
+}
+
+impl Precedence {
+    /// The next tightest level.
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::Lowest => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::BitOr,
+            Precedence::BitOr => Precedence::BitAnd,
+            Precedence::BitAnd => Precedence::Additive,
+            Precedence::Additive => Precedence::Multiplicative,
+            Precedence::Multiplicative | Precedence::Prefix => Precedence::Prefix,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Infix {
+    Binary(BinaryOp),
+    /// A plain (`None`) or compound assignment.
+    Assign(Option<BinaryOp>),
+}
+
+/// Look up an infix operator in the precedence table.
+fn infix_operator(kind: &TokenKind) -> Option<(Infix, Precedence)> {
+    let binary = |op, precedence| Some((Infix::Binary(op), precedence));
+
+    match kind {
+        TokenKind::Equals => Some((Infix::Assign(None), Precedence::Assignment)),
+        TokenKind::PlusEquals => Some((Infix::Assign(Some(BinaryOp::Add)), Precedence::Assignment)),
+        TokenKind::MinusEquals => Some((Infix::Assign(Some(BinaryOp::Subtract)), Precedence::Assignment)),
+        TokenKind::DoublePipe => binary(BinaryOp::Or, Precedence::Or),
+        TokenKind::DoubleAmpersand => binary(BinaryOp::And, Precedence::And),
+        TokenKind::DoubleEquals => binary(BinaryOp::Equal, Precedence::Equality),
+        TokenKind::NotEquals => binary(BinaryOp::NotEqual, Precedence::Equality),
+        TokenKind::OpenAngle => binary(BinaryOp::Less, Precedence::Comparison),
+        TokenKind::LessThanEquals => binary(BinaryOp::LessEqual, Precedence::Comparison),
+        TokenKind::CloseAngle => binary(BinaryOp::Greater, Precedence::Comparison),
+        TokenKind::GreaterThanEquals => binary(BinaryOp::GreaterEqual, Precedence::Comparison),
+        TokenKind::Pipe => binary(BinaryOp::BitOr, Precedence::BitOr),
+        TokenKind::Ampersand => binary(BinaryOp::BitAnd, Precedence::BitAnd),
+        TokenKind::Plus => binary(BinaryOp::Add, Precedence::Additive),
+        TokenKind::Minus => binary(BinaryOp::Subtract, Precedence::Additive),
+        TokenKind::Asterisk => binary(BinaryOp::Multiply, Precedence::Multiplicative),
+        TokenKind::Slash => binary(BinaryOp::Divide, Precedence::Multiplicative),
+        _ => None,
+    }
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn parse_expression(&mut self) -> Result<Expr> {
+        self.parse_expression_above(Precedence::Lowest)
+    }
+
+    /// Parse an expression, only taking infix operators which bind at least
+    /// as tightly as `min`.
+    fn parse_expression_above(&mut self, min: Precedence) -> Result<Expr> {
+        let mut lhs = self.parse_prefix()?;
+
+        loop {
+            if self.check(&TokenKind::MinusMinus) && !self.on_new_line() {
+                self.split_token(TokenKind::Minus, TokenKind::Minus);
+            }
+
+            let (op, precedence) = match self.peek().and_then(infix_operator) {
+                Some(infix) => infix,
+                None => break,
+            };
+            if precedence < min || self.on_new_line() {
+                break;
+            }
+            self.advance();
+
+            lhs = match op {
+                Infix::Binary(op) => {
+                    let rhs = self.parse_expression_above(precedence.next())?;
+                    let span = self.span_from(lhs.span);
+                    Expr { kind: ExprKind::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) }, span }
+                },
+                Infix::Assign(op) => {
+                    if !is_assignable(&lhs) {
+                        return Err(Error::InvalidAssignmentTarget { span: lhs.span });
+                    }
+
+                    // right associative, so `a = b = c` is `a = (b = c)`
+                    let value = self.parse_expression_above(precedence)?;
+                    let span = self.span_from(lhs.span);
+                    Expr { kind: ExprKind::Assign { op, target: Box::new(lhs), value: Box::new(value) }, span }
+                },
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_prefix(&mut self) -> Result<Expr> {
+        if self.check(&TokenKind::MinusMinus) {
+            self.split_token(TokenKind::Minus, TokenKind::Minus);
+        }
+
+        let op = match self.peek() {
+            Some(TokenKind::Minus) => UnaryOp::Negate,
+            Some(TokenKind::Exclamation) => UnaryOp::Not,
+            _ => return self.parse_postfix(),
+        };
+        let start = self.advance().unwrap().span;
+
+        let operand = self.parse_expression_above(Precedence::Prefix)?;
+        Ok(Expr { kind: ExprKind::Unary { op, operand: Box::new(operand) }, span: self.span_from(start) })
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            let start = expr.span;
+            let kind = match self.peek() {
+                Some(TokenKind::Dot) => {
+                    self.advance();
+                    let name = self.expect_ident("a member name")?;
+                    ExprKind::Member { object: Box::new(expr), name }
+                },
+                _ if self.on_new_line() => break,
+                Some(TokenKind::Hash) => {
+                    self.advance();
+                    let name = self.expect_ident("a companion type")?;
+                    ExprKind::Companion { parent: Box::new(expr), name }
+                },
+                Some(TokenKind::OpenParen) => {
+                    let args = self.parse_arguments()?;
+                    ExprKind::Call { callee: Box::new(expr), args }
+                },
+                Some(TokenKind::OpenSquare) => {
+                    self.advance();
+                    let index = self.with_constructs(true, Parser::parse_expression)?;
+                    self.expect(&TokenKind::CloseSquare)?;
+                    ExprKind::Index { object: Box::new(expr), index: Box::new(index) }
+                },
+                Some(TokenKind::Question) => {
+                    self.advance();
+                    ExprKind::Postfix { op: PostfixOp::Propagate, operand: Box::new(expr) }
+                },
+                Some(TokenKind::Throws) => {
+                    // `x?!` rather than a throws clause
+                    self.split_token(TokenKind::Question, TokenKind::Exclamation);
+                    continue;
+                },
+                Some(TokenKind::Exclamation) => {
+                    self.advance();
+                    ExprKind::Postfix { op: PostfixOp::AssertNotNil, operand: Box::new(expr) }
+                },
+                _ => break,
+            };
+
+            expr = Expr { kind, span: self.span_from(start) };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<Argument>> {
+        self.expect(&TokenKind::OpenParen)?;
+
+        let args = self.with_constructs(true, |p| {
+            p.parse_separated(&TokenKind::CloseParen, |p| {
+                let named = matches!(p.peek(), Some(TokenKind::Identifier(_)))
+                    && p.peek_nth(1) == Some(&TokenKind::Equals);
+
+                let name = if named {
+                    let name = p.expect_ident("an argument name")?;
+                    p.expect(&TokenKind::Equals)?;
+                    Some(name)
+                } else {
+                    None
+                };
+
+                Ok(Argument { name, value: p.parse_expression()? })
+            })
+        })?;
+
+        self.expect(&TokenKind::CloseParen)?;
+        Ok(args)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let start = self.peek_span();
+
+        if let Some(literal) = self.parse_literal() {
+            return Ok(Expr { kind: ExprKind::Literal(literal), span: start });
+        }
+
+        let kind = match self.peek() {
+            Some(TokenKind::StringStart) => ExprKind::String(self.parse_string()?),
+            Some(TokenKind::Identifier(_)) => {
+                let path = self.parse_path()?;
+
+                if self.check(&TokenKind::OpenCurly) && !self.on_new_line() && self.constructs_allowed() {
+                    let fields = self.parse_construct_fields()?;
+                    ExprKind::Construct { ty: path, fields }
+                } else {
+                    ExprKind::Path(path)
+                }
+            },
+            Some(TokenKind::SelfValue) => {
+                self.advance();
+                ExprKind::SelfValue
+            },
+            Some(TokenKind::OpenParen) if self.at_lambda() => self.parse_lambda()?,
+            Some(TokenKind::OpenParen) => {
+                self.advance();
+                let mut exprs = self.with_constructs(true, |p| {
+                    p.parse_separated(&TokenKind::CloseParen, Parser::parse_expression)
+                })?;
+                let trailing_comma = self.previous() == Some(&TokenKind::Comma);
+                self.expect(&TokenKind::CloseParen)?;
+
+                if exprs.len() == 1 && !trailing_comma {
+                    let mut inner = exprs.remove(0);
+                    inner.span = self.span_from(start);
+                    return Ok(inner);
+                }
+                ExprKind::Tuple(exprs)
+            },
+            Some(TokenKind::If) => self.parse_if()?,
+            Some(TokenKind::When) => self.parse_when()?,
+            Some(TokenKind::OpenCurly) => ExprKind::Block(self.parse_block()?),
+            Some(TokenKind::Throw) => {
+                self.advance();
+                ExprKind::Throw(Box::new(self.parse_expression()?))
+            },
+            _ => return Err(self.unexpected("an expression")),
+        };
+
+        Ok(Expr { kind, span: self.span_from(start) })
+    }
+
+    /// An interpolated string, e.g. `"Hello, ${name}!"`.
+    fn parse_string(&mut self) -> Result<Vec<StringPart>> {
+        self.expect(&TokenKind::StringStart)?;
+        let mut parts = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(TokenKind::StringFragment(text)) => {
+                    parts.push(StringPart::Text(text.clone()));
+                    self.advance();
+                },
+                Some(TokenKind::Dollar) => {
+                    self.advance();
+
+                    let expr = if self.eat(&TokenKind::OpenCurly).is_some() {
+                        let expr = self.with_constructs(true, Parser::parse_expression)?;
+                        self.expect(&TokenKind::CloseCurly)?;
+                        expr
+                    } else {
+                        let name = self.expect_ident("a name")?;
+                        let span = name.span;
+                        Expr { kind: ExprKind::Path(Path { segments: vec![name], span }), span }
+                    };
+                    parts.push(StringPart::Interpolation(expr));
+                },
+                Some(TokenKind::StringEnd) => {
+                    self.advance();
+                    return Ok(parts);
+                },
+                _ => return Err(self.unexpected("the end of the string")),
+            }
+        }
+    }
+
+    fn parse_construct_fields(&mut self) -> Result<Vec<FieldInit>> {
+        self.expect(&TokenKind::OpenCurly)?;
+
+        let fields = self.parse_separated(&TokenKind::CloseCurly, |p| {
+            let name = p.expect_ident("a field name")?;
+            let value = if p.eat(&TokenKind::Colon).is_some() {
+                Some(p.parse_expression()?)
+            } else {
+                None
+            };
+            Ok(FieldInit { name, value })
+        })?;
+
+        self.expect(&TokenKind::CloseCurly)?;
+        Ok(fields)
+    }
+
+    /// Are we at the `(a, b) ->` at the start of a lambda?
+    fn at_lambda(&self) -> bool {
+        let mut n = 1;
+
+        loop {
+            match self.peek_nth(n) {
+                Some(TokenKind::Identifier(_)) | Some(TokenKind::Comma) => n += 1,
+                Some(TokenKind::CloseParen) => return self.peek_nth(n + 1) == Some(&TokenKind::Arrow),
+                _ => return false,
+            }
+        }
+    }
+
+    fn parse_lambda(&mut self) -> Result<ExprKind> {
+        self.expect(&TokenKind::OpenParen)?;
+        let params = self.parse_separated(&TokenKind::CloseParen, |p| p.expect_ident("a parameter name"))?;
+        self.expect(&TokenKind::CloseParen)?;
+        self.expect(&TokenKind::Arrow)?;
+
+        let body = self.parse_expression()?;
+        Ok(ExprKind::Lambda { params, body: Box::new(body) })
+    }
+
+    fn parse_if(&mut self) -> Result<ExprKind> {
+        self.expect(&TokenKind::If)?;
+        let condition = self.with_constructs(false, Parser::parse_expression)?;
+        let then = self.parse_block()?;
+
+        let otherwise = if self.eat(&TokenKind::Else).is_some() {
+            let start = self.peek_span();
+            let kind = if self.check(&TokenKind::If) {
+                self.parse_if()?
+            } else {
+                ExprKind::Block(self.parse_block()?)
+            };
+            Some(Box::new(Expr { kind, span: self.span_from(start) }))
+        } else {
+            None
+        };
+
+        Ok(ExprKind::If { condition: Box::new(condition), then, otherwise })
+    }
+
+    fn parse_when(&mut self) -> Result<ExprKind> {
+        self.expect(&TokenKind::When)?;
+        let subject = self.with_constructs(false, Parser::parse_expression)?;
+        self.expect(&TokenKind::OpenCurly)?;
+
+        let arms = self.with_constructs(true, |p| {
+            let mut arms = Vec::new();
+
+            while !p.check(&TokenKind::CloseCurly) {
+                if !arms.is_empty() && !p.on_new_line() {
+                    return Err(p.unexpected("a new line or `}`"));
+                }
+
+                let pattern = p.parse_pattern()?;
+                p.expect(&TokenKind::Arrow)?;
+                let body = p.parse_expression()?;
+                let span = p.span_from(pattern.span);
+                arms.push(WhenArm { pattern, body, span });
+            }
+
+            Ok(arms)
+        })?;
+
+        self.expect(&TokenKind::CloseCurly)?;
+        Ok(ExprKind::When { subject: Box::new(subject), arms })
+    }
+}
+
+/// Can `expr` be on the left hand side of an assignment?
+fn is_assignable(expr: &Expr) -> bool {
+    matches!(expr.kind, ExprKind::Path(_) | ExprKind::Member { .. } | ExprKind::Index { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use maple_lexer::CodeMap;
+    use crate::errors::Error;
+    use crate::parser::parse_expression;
+
+    fn sexp(src: &str) -> String {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", src);
+
+        parse_expression(&fm).unwrap().to_string()
+    }
+
+    #[test]
+    fn operators_follow_the_precedence_table() {
+        let inputs = vec![
+            ("a = b || c", "(= a (|| b c))"),
+            ("a || b && c", "(|| a (&& b c))"),
+            ("a && b == c", "(&& a (== b c))"),
+            ("a == b < c", "(== a (< b c))"),
+            ("a < b | c", "(< a (| b c))"),
+            ("a | b & c", "(| a (& b c))"),
+            ("a & b + c", "(& a (+ b c))"),
+            ("a + b * c", "(+ a (* b c))"),
+            ("a * -b", "(* a (- b))"),
+            ("-a.b(c)", "(- (call (. a b) c))"),
+            ("!a?", "(! (postfix ? a))"),
+            ("flags & MASK == 0", "(== (& flags MASK) 0)"),
+            ("a == b || c < d && !e", "(|| (== a b) (&& (< c d) (! e)))"),
+            ("(1 + 2) * 3", "(* (+ 1 2) 3)"),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn operators_have_associativity() {
+        let inputs = vec![
+            ("1 - 2 - 3", "(- (- 1 2) 3)"),
+            ("a / b * c", "(* (/ a b) c)"),
+            ("a || b || c", "(|| (|| a b) c)"),
+            ("a = b += c", "(= a (+= b c))"),
+            ("- -a", "(- (- a))"),
+            ("--a", "(- (- a))"),
+            ("a--b", "(- a (- b))"),
+            ("a --b", "(- a (- b))"),
+            ("---a", "(- (- (- a)))"),
+            ("!!a", "(! (! a))"),
+            ("a!!", "(postfix ! (postfix ! a))"),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn index_and_nullish_operators() {
+        let inputs = vec![
+            ("a[i + 1]", "(index a (+ i 1))"),
+            ("a.b[0].c", "(. (index (. a b) 0) c)"),
+            ("matrix[i][j]", "(index (index matrix i) j)"),
+            ("a.b?.c", "(. (postfix ? (. a b)) c)"),
+            ("list[0]!", "(postfix ! (index list 0))"),
+            ("f(x)?", "(postfix ? (call f x))"),
+            ("x?!", "(postfix ! (postfix ? x))"),
+            ("f()?!.a", "(. (postfix ! (postfix ? (call f))) a)"),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let inputs = vec![
+            ("money += paycheck.afterTax", "(+= money (. paycheck afterTax))"),
+            ("a.b -= 1", "(-= (. a b) 1)"),
+            ("a[i] = b + c", "(= (index a i) (+ b c))"),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn only_places_can_be_assigned_to() {
+        for src in ["a + b = c", "f() = 1", "-a += 1", "1 = 2"] {
+            let mut map = CodeMap::new();
+            let fm = map.insert_file("foo.maple", src);
+
+            let err = parse_expression(&fm).unwrap_err();
+
+            assert!(matches!(err, Error::InvalidAssignmentTarget { .. }), "{}: {:?}", src, err);
+            assert_eq!(err.code(), "E0102");
+        }
+    }
+
+    #[test]
+    fn operators_on_the_next_line_start_a_new_statement() {
+        let inputs = vec![
+            ("{\n    a\n    -b\n}", "(block a (- b))"),
+            ("{\n    a\n    (b)\n}", "(block a b)"),
+            ("{\n    a\n    !b\n}", "(block a (! b))"),
+            ("{\n    a\n        .b\n}", "(block (. a b))"),
+            ("{\n    a +\n        b\n}", "(block (+ a b))"),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn calls_and_access() {
+        let inputs = vec![
+            ("f()", "(call f)"),
+            ("list.reduce(f, init = nil)", "(call (. list reduce) f (named init nil))"),
+            ("Paycheck#Error(\"oops\")", "(call (# Paycheck Error) \"oops\")"),
+            ("sys::io::STDOUT.writeLine(msg)", "(call (. sys::io::STDOUT writeLine) msg)"),
+            ("a\n    .b\n    .c", "(. (. a b) c)"),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn compound_expressions() {
+        let inputs = vec![
+            ("(a, b) -> a + b", "(lambda (a b) (+ a b))"),
+            ("(a, b)", "(tuple a b)"),
+            ("(a,)", "(tuple a)"),
+            ("Person { name, age: 42 }", "(construct Person name (age 42))"),
+            ("if a { b } else if c { d } else { e }", "(if a (block b) (if c (block d) (block e)))"),
+            ("if x == Foo { y }", "(if (== x Foo) (block y))"),
+            ("when x {\n    1 -> a\n    _ -> b\n}", "(when x (-> 1 a) (-> _ b))"),
+            ("\"Hi $name, ${a + b}\"", "(string \"Hi \" name \", \" (+ a b))"),
+            ("\"$b\"", "(string b)"),
+            ("\"$b's\"", "(string b \"'s\")"),
+            ("throw Error(\"oops\")", "(throw (call Error \"oops\"))"),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! The parser for the Maple language, turning the tokens from `maple-lexer`
+//! into an abstract syntax tree.
+//!
+//! ```rust
+//! use maple_lexer::CodeMap;
+//! use maple_parser::ast::ItemKind;
+//!
+//! let mut map = CodeMap::new();
+//! let file = map.insert_file("main.maple", "object Person {\n    public val name: String\n}");
+//! let ast = maple_parser::parse_file(&file).unwrap();
+//!
+//! assert!(matches!(ast.items[0].kind, ItemKind::Object(_)));
+//! ```
+
+pub mod ast;
+pub mod errors;
+mod parser;
+mod expr;
+mod sexp;
+
+pub use crate::errors::{Error, Result};
+pub use crate::parser::{parse_expression, parse_file};
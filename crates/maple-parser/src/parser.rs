@@ -0,0 +1,978 @@
+//! A recursive descent parser turning a file's tokens into an AST.
+//!
+//! Maple doesn't have statement terminators, so the parser needs to know
+//! where lines start. A binary operator or a call's opening bracket has to
+//! be on the same line as whatever comes before it, which stops the `|` at
+//! the start of a member like `|self| fun ...` from being read as part of
+//! the previous member's body.
+
+use std::collections::HashMap;
+use maple_lexer::{FileMap, Span, Token, TokenKind};
+use crate::ast::*;
+use crate::errors::*;
+
+/// Parse a whole file.
+pub fn parse_file(file: &FileMap) -> Result<File> {
+    let mut parser = Parser::new(file, file.tokenize()?);
+    parser.parse_file()
+}
+
+/// Parse a file containing a single expression, mainly useful for testing.
+pub fn parse_expression(file: &FileMap) -> Result<Expr> {
+    let mut parser = Parser::new(file, file.tokenize()?);
+    let expr = parser.parse_expression()?;
+
+    if parser.at_end() {
+        Ok(expr)
+    } else {
+        Err(parser.unexpected("the end of the expression"))
+    }
+}
+
+/// Where a declaration is being parsed, which limits what it can be.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Context {
+    TopLevel,
+    /// Inside a `tuple`, `enum` or `object`.
+    Member,
+    /// Inside a `companion` block, which can only hold types.
+    Companion,
+}
+
+pub(crate) struct Parser<'a> {
+    file: &'a FileMap,
+    tokens: Vec<Token>,
+    /// Whether each token is the first one on its line.
+    first_on_line: Vec<bool>,
+    position: usize,
+    /// Set while parsing something followed by a block (e.g. an `if`
+    /// condition), where `Name {` must be the end of the expression rather
+    /// than the start of a `Construct`.
+    no_construct: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(file: &'a FileMap, tokens: Vec<Token>) -> Parser<'a> {
+        let src = file.contents();
+        let mut first_on_line = Vec::with_capacity(tokens.len());
+        let mut previous_end = 0;
+
+        for token in &tokens {
+            let range = file.range_of(token.span).expect("Tokens must come from the FileMap");
+            first_on_line.push(previous_end == 0 || src[previous_end..range.start].contains('\n'));
+            previous_end = range.end;
+        }
+
+        Parser { file, tokens, first_on_line, position: 0, no_construct: false }
+    }
+
+    pub(crate) fn parse_file(&mut self) -> Result<File> {
+        let mut items = Vec::new();
+
+        while !self.at_end() {
+            items.push(self.parse_item(Context::TopLevel)?);
+        }
+
+        let span = self.file.insert_span(0, self.file.contents().len());
+        Ok(File { items, span })
+    }
+
+    // Declarations
+
+    fn parse_item(&mut self, context: Context) -> Result<Item> {
+        let doc = self.parse_doc_comments();
+        let start = self.peek_span();
+
+        let receiver = if self.check(&TokenKind::Pipe) {
+            Some(self.parse_receiver()?)
+        } else {
+            None
+        };
+
+        let visibility = match self.peek() {
+            Some(TokenKind::Public) => Some(Visibility::Public),
+            Some(TokenKind::Private) => Some(Visibility::Private),
+            _ => None,
+        };
+        if visibility.is_some() {
+            self.advance();
+        }
+
+        let kind = match self.peek() {
+            Some(TokenKind::Trait) | Some(TokenKind::Impl) | Some(TokenKind::Fun) | Some(TokenKind::Op) => {
+                ItemKind::Function(Box::new(self.parse_function(receiver)?))
+            },
+            _ if receiver.is_some() => return Err(self.unexpected("a function")),
+            Some(TokenKind::Val) => ItemKind::Property(Box::new(self.parse_property()?)),
+            Some(TokenKind::Tuple) => ItemKind::Tuple(self.parse_tuple()?),
+            Some(TokenKind::Enum) => ItemKind::Enum(self.parse_enum()?),
+            Some(TokenKind::Object) => ItemKind::Object(self.parse_object()?),
+            Some(TokenKind::Companion) => ItemKind::Companion(self.parse_companion()?),
+            _ => return Err(self.unexpected("a declaration")),
+        };
+
+        let item = Item { doc, visibility, kind, span: self.span_from(start) };
+        check_placement(&item, context)?;
+
+        Ok(item)
+    }
+
+    fn parse_doc_comments(&mut self) -> Option<String> {
+        let mut lines = Vec::new();
+
+        while let Some(TokenKind::DocComment(text)) = self.peek() {
+            lines.push(text.clone());
+            self.advance();
+        }
+
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
+
+    /// The `|...|` before a member function.
+    fn parse_receiver(&mut self) -> Result<Receiver> {
+        let start = self.expect(&TokenKind::Pipe)?;
+
+        let kind = match self.peek() {
+            Some(TokenKind::OpenParen) => {
+                self.advance();
+                let elements = self.parse_separated(&TokenKind::CloseParen, |p| {
+                    if p.eat(&TokenKind::Underscore).is_some() {
+                        Ok(None)
+                    } else {
+                        p.expect_ident("a name or `_`").map(Some)
+                    }
+                })?;
+                self.expect(&TokenKind::CloseParen)?;
+                ReceiverKind::Tuple(elements)
+            },
+            Some(TokenKind::OpenCurly) => {
+                self.advance();
+                let fields = self.parse_separated(&TokenKind::CloseCurly, |p| p.expect_ident("a field name"))?;
+                self.expect(&TokenKind::CloseCurly)?;
+                ReceiverKind::Fields(fields)
+            },
+            Some(TokenKind::SelfValue) => {
+                self.advance();
+                let alias = if self.eat(&TokenKind::As).is_some() {
+                    Some(self.expect_ident("a name for `self`")?)
+                } else {
+                    None
+                };
+                ReceiverKind::SelfValue { alias }
+            },
+            _ => return Err(self.unexpected("`(`, `{` or `self`")),
+        };
+
+        self.expect(&TokenKind::Pipe)?;
+        let receiver = Receiver { kind, span: self.span_from(start) };
+        check_receiver(&receiver)?;
+
+        Ok(receiver)
+    }
+
+    fn parse_function(&mut self, receiver: Option<Receiver>) -> Result<Function> {
+        let is_trait = self.eat(&TokenKind::Trait).is_some();
+        let is_impl = self.eat(&TokenKind::Impl).is_some();
+        let is_operator = self.eat(&TokenKind::Op).is_some();
+        if !is_operator {
+            self.expect(&TokenKind::Fun)?;
+        }
+
+        let modifier = self.parse_modifier()?;
+        let name = self.expect_ident("a function name")?;
+
+        let generics = if self.check(&TokenKind::OpenAngle) {
+            self.parse_generics()?
+        } else {
+            Vec::new()
+        };
+
+        let params = if self.check(&TokenKind::OpenParen) {
+            Some(self.parse_params()?)
+        } else {
+            None
+        };
+
+        let return_type = if self.eat(&TokenKind::Colon).is_some() {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        let throws = if self.eat(&TokenKind::Throws).is_some() {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        let body = if self.eat(&TokenKind::Equals).is_some() {
+            Some(FunctionBody::Expr(self.parse_expression()?))
+        } else if self.check(&TokenKind::OpenCurly) {
+            Some(FunctionBody::Block(self.parse_block()?))
+        } else {
+            None
+        };
+
+        Ok(Function {
+            receiver,
+            is_trait,
+            is_impl,
+            is_operator,
+            modifier,
+            name,
+            generics,
+            params,
+            return_type,
+            throws,
+            body,
+        })
+    }
+
+    /// The word in brackets after a keyword, like `fun(get)`.
+    fn parse_modifier(&mut self) -> Result<Option<Ident>> {
+        if self.eat(&TokenKind::OpenParen).is_none() {
+            return Ok(None);
+        }
+
+        let modifier = self.expect_ident("a modifier")?;
+        self.expect(&TokenKind::CloseParen)?;
+
+        Ok(Some(modifier))
+    }
+
+    fn parse_generics(&mut self) -> Result<Vec<Ident>> {
+        self.expect(&TokenKind::OpenAngle)?;
+        let generics = self.parse_separated(&TokenKind::CloseAngle, |p| p.expect_ident("a type parameter"))?;
+        self.expect(&TokenKind::CloseAngle)?;
+
+        Ok(generics)
+    }
+
+    fn parse_params(&mut self) -> Result<Vec<Param>> {
+        self.expect(&TokenKind::OpenParen)?;
+        let params = self.parse_separated(&TokenKind::CloseParen, Parser::parse_param)?;
+        self.expect(&TokenKind::CloseParen)?;
+
+        Ok(params)
+    }
+
+    fn parse_param(&mut self) -> Result<Param> {
+        let start = self.peek_span();
+        let name = self.expect_ident("a parameter name")?;
+        self.expect(&TokenKind::Colon)?;
+        let ty = self.parse_type()?;
+
+        let default = if self.eat(&TokenKind::Equals).is_some() {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+
+        Ok(Param { name, ty, default, span: self.span_from(start) })
+    }
+
+    /// `val name: Type = value`, as either a member or a statement. At least
+    /// one of the type and value must be given.
+    fn parse_property(&mut self) -> Result<Property> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Val)?;
+        let modifier = self.parse_modifier()?;
+        let name = self.expect_ident("a property name")?;
+
+        let ty = if self.eat(&TokenKind::Colon).is_some() {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        let value = if self.eat(&TokenKind::Equals).is_some() {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+
+        if ty.is_none() && value.is_none() {
+            return Err(Error::UntypedProperty { name: name.name, span: self.span_from(start) });
+        }
+
+        Ok(Property { modifier, name, ty, value })
+    }
+
+    fn parse_tuple(&mut self) -> Result<TupleDecl> {
+        self.expect(&TokenKind::Tuple)?;
+        let name = self.expect_ident("a type name")?;
+
+        self.expect(&TokenKind::OpenParen)?;
+        let fields = self.parse_separated(&TokenKind::CloseParen, Parser::parse_type)?;
+        self.expect(&TokenKind::CloseParen)?;
+
+        let members = self.parse_optional_members()?;
+        Ok(TupleDecl { name, fields, members })
+    }
+
+    fn parse_enum(&mut self) -> Result<EnumDecl> {
+        self.expect(&TokenKind::Enum)?;
+        let name = self.expect_ident("a type name")?;
+
+        self.expect(&TokenKind::OpenSquare)?;
+        let variants = self.parse_separated(&TokenKind::CloseSquare, |p| p.expect_ident("a variant name"))?;
+        self.expect(&TokenKind::CloseSquare)?;
+
+        let members = self.parse_optional_members()?;
+        Ok(EnumDecl { name, variants, members })
+    }
+
+    fn parse_object(&mut self) -> Result<ObjectDecl> {
+        self.expect(&TokenKind::Object)?;
+        let name = self.expect_ident("a type name")?;
+        let members = self.parse_members(Context::Member)?;
+
+        Ok(ObjectDecl { name, members })
+    }
+
+    fn parse_companion(&mut self) -> Result<Companion> {
+        self.expect(&TokenKind::Companion)?;
+        let items = self.parse_members(Context::Companion)?;
+
+        Ok(Companion { items })
+    }
+
+    fn parse_optional_members(&mut self) -> Result<Vec<Item>> {
+        if self.check(&TokenKind::OpenCurly) {
+            self.parse_members(Context::Member)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// A list of declarations between curly braces.
+    fn parse_members(&mut self, context: Context) -> Result<Vec<Item>> {
+        self.expect(&TokenKind::OpenCurly)?;
+
+        let mut members = Vec::new();
+        while !self.check(&TokenKind::CloseCurly) {
+            if self.at_end() {
+                return Err(self.unexpected("`}`"));
+            }
+            members.push(self.parse_item(context)?);
+        }
+
+        self.expect(&TokenKind::CloseCurly)?;
+        Ok(members)
+    }
+
+    // Types
+
+    pub(crate) fn parse_type(&mut self) -> Result<Type> {
+        let start = self.peek_span();
+
+        let kind = match self.peek() {
+            Some(TokenKind::OpenParen) => {
+                self.advance();
+                let types = self.parse_separated(&TokenKind::CloseParen, Parser::parse_type)?;
+                self.expect(&TokenKind::CloseParen)?;
+                TypeKind::Tuple(types)
+            },
+            Some(TokenKind::Identifier(_)) => {
+                let path = self.parse_path()?;
+                let args = if self.check(&TokenKind::OpenAngle) && !self.on_new_line() {
+                    self.advance();
+                    let args = self.parse_separated(&TokenKind::CloseAngle, Parser::parse_type)?;
+                    self.expect(&TokenKind::CloseAngle)?;
+                    args
+                } else {
+                    Vec::new()
+                };
+                TypeKind::Named { path, args }
+            },
+            _ => return Err(self.unexpected("a type")),
+        };
+        let mut ty = Type { kind, span: self.span_from(start) };
+
+        while !self.on_new_line() {
+            let kind = match self.peek() {
+                Some(TokenKind::Hash) => {
+                    self.advance();
+                    let name = self.expect_ident("a companion type")?;
+                    TypeKind::Companion { parent: Box::new(ty), name }
+                },
+                Some(TokenKind::OpenSquare) => {
+                    self.advance();
+                    self.expect(&TokenKind::CloseSquare)?;
+                    TypeKind::Array(Box::new(ty))
+                },
+                Some(TokenKind::Question) => {
+                    self.advance();
+                    TypeKind::Nullable(Box::new(ty))
+                },
+                _ => break,
+            };
+            ty = Type { kind, span: self.span_from(start) };
+        }
+
+        Ok(ty)
+    }
+
+    /// Identifiers separated by `::`.
+    pub(crate) fn parse_path(&mut self) -> Result<Path> {
+        let start = self.peek_span();
+        let mut segments = vec![self.expect_ident("a name")?];
+
+        while self.eat(&TokenKind::DoubleColon).is_some() {
+            segments.push(self.expect_ident("a name")?);
+        }
+
+        Ok(Path { segments, span: self.span_from(start) })
+    }
+
+    // Patterns
+
+    pub(crate) fn parse_pattern(&mut self) -> Result<Pattern> {
+        let start = self.peek_span();
+
+        let kind = match self.peek() {
+            Some(TokenKind::Underscore) => {
+                self.advance();
+                PatternKind::Wildcard
+            },
+            Some(TokenKind::Identifier(_)) => PatternKind::Ident(self.expect_ident("a name")?),
+            Some(TokenKind::OpenParen) => {
+                self.advance();
+                let patterns = self.parse_separated(&TokenKind::CloseParen, Parser::parse_pattern)?;
+                self.expect(&TokenKind::CloseParen)?;
+                PatternKind::Tuple(patterns)
+            },
+            _ => match self.parse_literal() {
+                Some(literal) => PatternKind::Literal(literal),
+                None => return Err(self.unexpected("a pattern")),
+            },
+        };
+
+        Ok(Pattern { kind, span: self.span_from(start) })
+    }
+
+    // Blocks
+
+    pub(crate) fn parse_block(&mut self) -> Result<Block> {
+        let start = self.expect(&TokenKind::OpenCurly)?;
+
+        let statements = self.with_constructs(true, |p| {
+            let mut statements = Vec::new();
+            while !p.check(&TokenKind::CloseCurly) {
+                if !statements.is_empty() && !p.on_new_line() {
+                    return Err(p.unexpected("a new line or `}`"));
+                }
+                statements.push(p.parse_statement()?);
+            }
+            Ok(statements)
+        })?;
+
+        self.expect(&TokenKind::CloseCurly)?;
+        Ok(Block { statements, span: self.span_from(start) })
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement> {
+        if self.check(&TokenKind::Val) {
+            let start = self.peek_span();
+            let property = self.parse_property()?;
+            Ok(Statement::Val { property, span: self.span_from(start) })
+        } else {
+            Ok(Statement::Expr(self.parse_expression()?))
+        }
+    }
+
+    // Helpers
+
+    /// Run `parse` with `Construct` expressions allowed or not, e.g. to stop
+    /// the `{` after an `if` condition from being read as a `Construct`.
+    pub(crate) fn with_constructs<T, F>(&mut self, allowed: bool, parse: F) -> Result<T>
+        where F: FnOnce(&mut Parser<'a>) -> Result<T>,
+    {
+        let outer = std::mem::replace(&mut self.no_construct, !allowed);
+        let result = parse(self);
+        self.no_construct = outer;
+        result
+    }
+
+    pub(crate) fn constructs_allowed(&self) -> bool {
+        !self.no_construct
+    }
+
+    /// Parse a comma-separated list (allowing a trailing comma) up to, but
+    /// not including, the `close` token.
+    pub(crate) fn parse_separated<T, F>(&mut self, close: &TokenKind, mut parse: F) -> Result<Vec<T>>
+        where F: FnMut(&mut Parser<'a>) -> Result<T>,
+    {
+        let mut items = Vec::new();
+
+        while !self.check(close) {
+            items.push(parse(self)?);
+
+            if self.eat(&TokenKind::Comma).is_none() {
+                break;
+            }
+        }
+
+        Ok(items)
+    }
+
+    pub(crate) fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    pub(crate) fn peek(&self) -> Option<&TokenKind> {
+        self.peek_nth(0)
+    }
+
+    pub(crate) fn peek_nth(&self, n: usize) -> Option<&TokenKind> {
+        self.tokens.get(self.position + n).map(|tok| &tok.kind)
+    }
+
+    /// The last token we consumed.
+    pub(crate) fn previous(&self) -> Option<&TokenKind> {
+        self.position.checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map(|tok| &tok.kind)
+    }
+
+    pub(crate) fn check(&self, kind: &TokenKind) -> bool {
+        self.peek() == Some(kind)
+    }
+
+    /// Is the next token the first one on its line?
+    pub(crate) fn on_new_line(&self) -> bool {
+        self.first_on_line.get(self.position).copied().unwrap_or(true)
+    }
+
+    pub(crate) fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Split the next token into `first` (its first character) and `second`
+    /// (the rest). The lexer always takes the longest operator it can, so
+    /// this is for places where that's the wrong choice, like `--a`.
+    pub(crate) fn split_token(&mut self, first: TokenKind, second: TokenKind) {
+        let span = self.tokens[self.position].span;
+        let range = self.file.range_of(span).expect("Tokens must come from the FileMap");
+        let first = Token::new(self.file.insert_span(range.start, range.start + 1), first);
+        let second = Token::new(self.file.insert_span(range.start + 1, range.end), second);
+
+        self.tokens.splice(self.position..=self.position, [first, second]);
+        self.first_on_line.insert(self.position + 1, false);
+    }
+
+    /// Skip over the next token if it's `kind`, returning its span.
+    pub(crate) fn eat(&mut self, kind: &TokenKind) -> Option<Span> {
+        if self.check(kind) {
+            self.advance().map(|tok| tok.span)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn expect(&mut self, kind: &TokenKind) -> Result<Span> {
+        match self.eat(kind) {
+            Some(span) => Ok(span),
+            None => Err(self.unexpected(&describe(kind))),
+        }
+    }
+
+    pub(crate) fn expect_ident(&mut self, expected: &str) -> Result<Ident> {
+        match self.peek() {
+            Some(TokenKind::Identifier(name)) => {
+                let name = name.clone();
+                let span = self.advance().unwrap().span;
+                Ok(Ident { name, span })
+            },
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    /// Take the next token if it's a literal value.
+    pub(crate) fn parse_literal(&mut self) -> Option<Literal> {
+        let literal = match self.peek()? {
+            TokenKind::Integer { value, suffix } => Literal::Integer { value: *value, suffix: *suffix },
+            TokenKind::Decimal { value, suffix } => Literal::Decimal { value: *value, suffix: *suffix },
+            TokenKind::QuotedString(text) => Literal::String(text.clone()),
+            TokenKind::Character(ch) => Literal::Character(*ch),
+            TokenKind::Byte(byte) => Literal::Byte(*byte),
+            TokenKind::ByteString(bytes) => Literal::ByteString(bytes.clone()),
+            TokenKind::True => Literal::Bool(true),
+            TokenKind::False => Literal::Bool(false),
+            TokenKind::Nil => Literal::Nil,
+            _ => return None,
+        };
+
+        self.advance();
+        Some(literal)
+    }
+
+    /// An error for when the next token isn't what we `expected`.
+    pub(crate) fn unexpected(&self, expected: &str) -> Error {
+        let expected = expected.to_string();
+
+        match self.tokens.get(self.position) {
+            Some(tok) => Error::UnexpectedToken { found: tok.kind.clone(), expected, span: tok.span },
+            None => Error::UnexpectedEOF { expected, span: self.eof_span() },
+        }
+    }
+
+    /// The span of the next token, or the end of the file if there isn't
+    /// one.
+    pub(crate) fn peek_span(&self) -> Span {
+        self.tokens.get(self.position)
+            .map(|tok| tok.span)
+            .unwrap_or_else(|| self.eof_span())
+    }
+
+    /// The span from the start of `start` to the end of the last token we
+    /// consumed.
+    pub(crate) fn span_from(&self, start: Span) -> Span {
+        match self.position.checked_sub(1).and_then(|i| self.tokens.get(i)) {
+            Some(previous) => self.file.merge(start, previous.span),
+            None => start,
+        }
+    }
+
+    fn eof_span(&self) -> Span {
+        let len = self.file.contents().len();
+        self.file.insert_span(len, len)
+    }
+}
+
+/// Make sure a declaration is allowed where it was written.
+fn check_placement(item: &Item, context: Context) -> Result<()> {
+    let is_type = matches!(item.kind, ItemKind::Tuple(_) | ItemKind::Enum(_) | ItemKind::Object(_));
+
+    match (context, &item.kind) {
+        (Context::TopLevel, ItemKind::Companion(_)) => {
+            Err(Error::MisplacedItem { item: item.kind.declaration(), place: Place::TopLevel, span: item.span })
+        },
+        (Context::TopLevel, ItemKind::Function(function)) => match &function.receiver {
+            Some(receiver) => Err(Error::TopLevelReceiver { span: receiver.span }),
+            None => Ok(()),
+        },
+        (Context::Companion, _) if !is_type => {
+            Err(Error::MisplacedItem { item: item.kind.declaration(), place: Place::Companion, span: item.span })
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Make sure a receiver binds something, and doesn't bind the same name
+/// twice.
+fn check_receiver(receiver: &Receiver) -> Result<()> {
+    let bindings = receiver.bindings();
+
+    if bindings.is_empty() && !matches!(receiver.kind, ReceiverKind::SelfValue { .. }) {
+        return Err(Error::EmptyReceiver { span: receiver.span });
+    }
+
+    let mut seen: HashMap<&str, Span> = HashMap::new();
+    for binding in bindings {
+        if let Some(&first) = seen.get(binding.name.as_str()) {
+            return Err(Error::DuplicateBinding { name: binding.name.clone(), first, span: binding.span });
+        }
+        seen.insert(&binding.name, binding.span);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use maple_lexer::CodeMap;
+    use super::*;
+
+    fn parse(src: &str) -> Result<File> {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", src);
+        parse_file(&fm)
+    }
+
+    fn sexp(src: &str) -> String {
+        parse(src).unwrap().to_string()
+    }
+
+    /// Parse an object with a single member function using `receiver`,
+    /// keeping the `CodeMap` so spans can be looked up.
+    fn parse_receiver_src(receiver: &str) -> (CodeMap, Result<File>) {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", format!("object A {{\n    {} fun f() = 1\n}}", receiver));
+        let file = parse_file(&fm);
+        (map, file)
+    }
+
+    #[test]
+    fn parse_tuples() {
+        let inputs = vec![
+            ("tuple Point (Int, Int)", "(tuple Point (fields Int Int))\n"),
+            ("public tuple Error(String) {}", "(tuple Error public (fields String))\n"),
+            (
+                "tuple Name (String, String) {\n    |(first, _)| fun(get) first = first\n}",
+                "(tuple Name (fields String String)\n  (fun first (receiver (tuple first _)) (modifier get) (= first)))\n",
+            ),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn parse_enums() {
+        let inputs = vec![
+            ("enum Bit [Zero, One]", "(enum Bit (variants Zero One))\n"),
+            ("enum Bit [\n    Zero,\n    One,\n]", "(enum Bit (variants Zero One))\n"),
+            (
+                "enum Bit [Zero, One] {\n    |self| fun(get) isSet = self == One\n}",
+                "(enum Bit (variants Zero One)\n  (fun isSet (receiver self) (modifier get) (= (== self One))))\n",
+            ),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn parse_object_members() {
+        let src = "object Account {\n    public val owner: Name\n    private val balance: Int = 0\n    \
+                   val(static) LIMIT = 100\n    public fun(constructor) new(owner: Name) = Account { owner }\n}";
+
+        let got = sexp(src);
+
+        assert_eq!(got, "(object Account\n  \
+                         (val owner public (: Name))\n  \
+                         (val balance private (: Int) (= 0))\n  \
+                         (val LIMIT (modifier static) (= 100))\n  \
+                         (fun new public (modifier constructor) (params (owner Name)) (= (construct Account owner))))\n");
+    }
+
+    #[test]
+    fn parse_trait_functions() {
+        let inputs = vec![
+            ("trait fun to<T>(): T", "(fun to trait (generics T) (params) (returns T))\n"),
+            ("trait fun(get) displayName: String", "(fun displayName trait (modifier get) (returns String))\n"),
+            ("trait fun compare<T>(other: T): Int", "(fun compare trait (generics T) (params (other T)) (returns Int))\n"),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sexp(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn companions_hold_nested_types() {
+        let src = "object Paycheck {\n    companion {\n        public tuple Error(String)\n        \
+                   enum Kind [Weekly, Monthly]\n        object Builder {\n            companion {\n                \
+                   tuple Step(Int)\n            }\n        }\n    }\n}";
+
+        let got = sexp(src);
+
+        assert_eq!(got, "(object Paycheck\n  \
+                         (companion\n    \
+                         (tuple Error public (fields String))\n    \
+                         (enum Kind (variants Weekly Monthly))\n    \
+                         (object Builder\n      \
+                         (companion\n        \
+                         (tuple Step (fields Int))))))\n");
+    }
+
+    #[test]
+    fn companions_can_only_hold_types() {
+        let inputs = vec![
+            ("object A {\n    companion {\n        fun f() {}\n    }\n}", "Can't declare a function in a companion block"),
+            ("object A {\n    companion {\n        val x = 1\n    }\n}", "Can't declare a property in a companion block"),
+            ("object A {\n    companion {\n        companion {}\n    }\n}", "Can't declare a companion block in a companion block"),
+            ("companion {}", "Can't declare a companion block at the top level"),
+        ];
+
+        for (src, message) in inputs {
+            let err = parse(src).unwrap_err();
+
+            assert!(matches!(err, Error::MisplacedItem { .. }), "{}: {:?}", src, err);
+            assert_eq!(err.to_string(), message);
+            assert_eq!(err.code(), "E0103");
+        }
+    }
+
+    #[test]
+    fn properties_need_a_type_or_a_value() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "object A {\n    public val x\n}");
+
+        let err = parse_file(&fm).unwrap_err();
+
+        assert_eq!(err, Error::UntypedProperty { name: "x".to_string(), span: err.span() });
+        assert_eq!(map.lookup(err.span()), "val x");
+        assert_eq!(err.to_diagnostic().help, vec!["give it a type (e.g. `val x: Int`) or a value"]);
+    }
+
+    #[test]
+    fn parse_types() {
+        let got = sexp("val a: (String, Int)\nval b: Paycheck[]?\nval c: sys::io::Buffer<T>#Error");
+
+        assert_eq!(got, "(val a (: (String, Int)))\n(val b (: Paycheck[]?))\n(val c (: sys::io::Buffer<T>#Error))\n");
+    }
+
+    #[test]
+    fn doc_comments_are_attached_to_the_next_item() {
+        let got = parse("/// The answer\n/// to everything\nval answer = 42").unwrap();
+
+        assert_eq!(got.items[0].doc.as_deref(), Some(" The answer\n to everything"));
+    }
+
+    #[test]
+    fn items_have_spans() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "// comment\npublic val x: Int = 1\n");
+
+        let got = parse_file(&fm).unwrap();
+
+        assert_eq!(map.lookup(got.items[0].span), "public val x: Int = 1");
+    }
+
+    #[test]
+    fn operators_dont_continue_onto_the_next_line() {
+        let src = "object A {\n    |self| fun(get) a = 1\n    |self| fun(get) b = 2\n}";
+
+        let got = sexp(src);
+
+        assert_eq!(got, "(object A\n  (fun a (receiver self) (modifier get) (= 1))\n  (fun b (receiver self) (modifier get) (= 2)))\n");
+    }
+
+    #[test]
+    fn statements_need_their_own_line() {
+        let err = parse("fun f() {\n    a b\n}").unwrap_err();
+
+        match err {
+            Error::UnexpectedToken { found, expected, .. } => {
+                assert_eq!(found, TokenKind::Identifier("b".to_string()));
+                assert_eq!(expected, "a new line or `}`");
+            },
+            other => panic!("Unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn receivers_are_only_allowed_on_functions() {
+        let err = parse("object A {\n    |self| val x = 1\n}").unwrap_err();
+
+        assert_eq!(err.to_string(), "Expected a function, found keyword `val`");
+    }
+
+    #[test]
+    fn parse_receivers() {
+        let inputs = vec![
+            ("|(first, last)|", "(tuple first last)"),
+            ("|(_, last)|", "(tuple _ last)"),
+            ("|{name, age}|", "(fields name age)"),
+            ("|{name,}|", "(fields name)"),
+            ("|self|", "self"),
+            ("|self as lhs|", "(self as lhs)"),
+        ];
+
+        for (receiver, should_be) in inputs {
+            let (map, file) = parse_receiver_src(receiver);
+            let file = file.unwrap();
+            let function = match &file.items[0].kind {
+                ItemKind::Object(object) => match &object.members[0].kind {
+                    ItemKind::Function(function) => function.clone(),
+                    other => panic!("Expected a function, found {:?}", other),
+                },
+                other => panic!("Expected an object, found {:?}", other),
+            };
+
+            let got = function.receiver.expect("There should be a receiver");
+            assert_eq!(got.to_string(), should_be, "{}", receiver);
+            assert_eq!(map.lookup(got.span), receiver);
+        }
+    }
+
+    #[test]
+    fn receivers_cant_bind_a_name_twice() {
+        let inputs = vec!["|(a, a)|", "|(a, _, a)|", "|{a, b, a}|"];
+
+        for receiver in inputs {
+            let (map, file) = parse_receiver_src(receiver);
+
+            let err = file.unwrap_err();
+
+            assert_eq!(err.to_string(), "`a` is bound more than once in the same receiver");
+            assert_eq!(err.code(), "E0105");
+            let diagnostic = err.to_diagnostic();
+            assert_eq!(diagnostic.secondary.len(), 1);
+            assert_eq!(map.lookup(diagnostic.primary.span), "a");
+            assert_eq!(map.lookup(diagnostic.secondary[0].span), "a");
+            assert_ne!(diagnostic.primary.span, diagnostic.secondary[0].span);
+        }
+    }
+
+    #[test]
+    fn receivers_have_to_bind_something() {
+        for receiver in ["|()|", "|{}|", "|(_, _)|"] {
+            let err = parse_receiver_src(receiver).1.unwrap_err();
+
+            assert!(matches!(err, Error::EmptyReceiver { .. }), "{}: {:?}", receiver, err);
+            assert_eq!(err.code(), "E0106");
+        }
+    }
+
+    #[test]
+    fn malformed_receivers() {
+        let inputs = vec![
+            ("|self as|", "Expected a name for `self`, found `|`"),
+            ("|(a.b)|", "Expected `)`, found `.`"),
+            ("|(1, a)|", "Expected a name or `_`, found a number"),
+            ("|{_}|", "Expected a field name, found `_`"),
+            ("|name|", "Expected `(`, `{` or `self`, found identifier `name`"),
+            ("|self", "Expected `|`, found keyword `fun`"),
+        ];
+
+        for (receiver, message) in inputs {
+            let err = parse_receiver_src(receiver).1.unwrap_err();
+
+            assert_eq!(err.to_string(), message, "{}", receiver);
+        }
+    }
+
+    #[test]
+    fn receivers_cant_be_used_at_the_top_level() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "|self as lhs| fun f() = 1");
+
+        let err = parse_file(&fm).unwrap_err();
+
+        assert_eq!(err.to_string(), "Top level functions can't have a receiver");
+        assert_eq!(err.code(), "E0107");
+        assert_eq!(map.lookup(err.span()), "|self as lhs|");
+        assert_eq!(err.to_diagnostic().notes.len(), 1);
+    }
+
+    #[test]
+    fn running_out_of_input_is_an_error() {
+        let mut map = CodeMap::new();
+        let fm = map.insert_file("foo.maple", "object Person {\n    val name: Name\n");
+
+        let err = parse_file(&fm).unwrap_err();
+
+        assert!(matches!(err, Error::UnexpectedEOF { .. }), "{:?}", err);
+        assert_eq!(err.code(), "E0101");
+        assert_eq!(map.lookup(err.span()), "");
+    }
+
+    #[test]
+    fn lexer_errors_are_passed_through() {
+        let err = parse("val x = `").unwrap_err();
+
+        assert!(matches!(err, Error::Lex(_)), "{:?}", err);
+        assert_eq!(err.code(), "E0002");
+    }
+}
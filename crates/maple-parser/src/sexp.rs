@@ -0,0 +1,351 @@
+//! Displaying the AST as S-expressions, which makes it easy to see how
+//! something was parsed (e.g. `1 + 2 * 3` is shown as `(+ 1 (* 2 3))`).
+//!
+//! Spans are left out. Declarations are written one per line, with their
+//! members indented underneath them, while everything else stays on one
+//! line. Types are written the same way they are in source code.
+
+use std::fmt::{self, Display, Formatter};
+use crate::ast::*;
+
+impl Display for File {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            write_item(f, item, 0)?;
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Item {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_item(f, self, 0)
+    }
+}
+
+fn write_item(f: &mut Formatter<'_>, item: &Item, depth: usize) -> fmt::Result {
+    write!(f, "{}(", "  ".repeat(depth))?;
+
+    let members = match &item.kind {
+        ItemKind::Function(function) => {
+            let keyword = if function.is_operator { "op" } else { "fun" };
+            write!(f, "{} {}", keyword, function.name)?;
+            write_item_header(f, item)?;
+            write_function(f, function)?;
+            &[][..]
+        },
+        ItemKind::Property(property) => {
+            write!(f, "val {}", property.name)?;
+            write_item_header(f, item)?;
+            write_property(f, property)?;
+            &[][..]
+        },
+        ItemKind::Tuple(tuple) => {
+            write!(f, "tuple {}", tuple.name)?;
+            write_item_header(f, item)?;
+            write!(f, " (fields {})", Spaced(&tuple.fields))?;
+            &tuple.members[..]
+        },
+        ItemKind::Enum(enumeration) => {
+            write!(f, "enum {}", enumeration.name)?;
+            write_item_header(f, item)?;
+            write!(f, " (variants {})", Spaced(&enumeration.variants))?;
+            &enumeration.members[..]
+        },
+        ItemKind::Object(object) => {
+            write!(f, "object {}", object.name)?;
+            write_item_header(f, item)?;
+            &object.members[..]
+        },
+        ItemKind::Companion(companion) => {
+            write!(f, "companion")?;
+            write_item_header(f, item)?;
+            &companion.items[..]
+        },
+    };
+
+    for member in members {
+        writeln!(f)?;
+        write_item(f, member, depth + 1)?;
+    }
+
+    write!(f, ")")
+}
+
+fn write_item_header(f: &mut Formatter<'_>, item: &Item) -> fmt::Result {
+    match item.visibility {
+        Some(Visibility::Public) => write!(f, " public")?,
+        Some(Visibility::Private) => write!(f, " private")?,
+        None => {},
+    }
+
+    if let Some(doc) = &item.doc {
+        write!(f, " (doc {:?})", doc)?;
+    }
+
+    Ok(())
+}
+
+fn write_function(f: &mut Formatter<'_>, function: &Function) -> fmt::Result {
+    if function.is_trait {
+        write!(f, " trait")?;
+    }
+    if function.is_impl {
+        write!(f, " impl")?;
+    }
+    if let Some(receiver) = &function.receiver {
+        write!(f, " (receiver {})", receiver)?;
+    }
+    if let Some(modifier) = &function.modifier {
+        write!(f, " (modifier {})", modifier)?;
+    }
+    if !function.generics.is_empty() {
+        write!(f, " (generics {})", Spaced(&function.generics))?;
+    }
+    if let Some(params) = &function.params {
+        write!(f, " (params")?;
+        for param in params {
+            write!(f, " ({} {}", param.name, param.ty)?;
+            if let Some(default) = &param.default {
+                write!(f, " {}", default)?;
+            }
+            write!(f, ")")?;
+        }
+        write!(f, ")")?;
+    }
+    if let Some(return_type) = &function.return_type {
+        write!(f, " (returns {})", return_type)?;
+    }
+    if let Some(throws) = &function.throws {
+        write!(f, " (throws {})", throws)?;
+    }
+
+    match &function.body {
+        Some(FunctionBody::Expr(expr)) => write!(f, " (= {})", expr),
+        Some(FunctionBody::Block(block)) => write!(f, " {}", block),
+        None => Ok(()),
+    }
+}
+
+/// Everything in a `val` after its name.
+fn write_property(f: &mut Formatter<'_>, property: &Property) -> fmt::Result {
+    if let Some(modifier) = &property.modifier {
+        write!(f, " (modifier {})", modifier)?;
+    }
+    if let Some(ty) = &property.ty {
+        write!(f, " (: {})", ty)?;
+    }
+    if let Some(value) = &property.value {
+        write!(f, " (= {})", value)?;
+    }
+
+    Ok(())
+}
+
+impl Display for Ident {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                f.write_str("::")?;
+            }
+            write!(f, "{}", segment)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            TypeKind::Named { path, args } if args.is_empty() => write!(f, "{}", path),
+            TypeKind::Named { path, args } => write!(f, "{}<{}>", path, Separated(args, ", ")),
+            TypeKind::Companion { parent, name } => write!(f, "{}#{}", parent, name),
+            TypeKind::Tuple(types) => write!(f, "({})", Separated(types, ", ")),
+            TypeKind::Array(element) => write!(f, "{}[]", element),
+            TypeKind::Nullable(inner) => write!(f, "{}?", inner),
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            PatternKind::Wildcard => write!(f, "_"),
+            PatternKind::Ident(name) => write!(f, "{}", name),
+            PatternKind::Literal(literal) => write!(f, "{}", literal),
+            PatternKind::Tuple(patterns) => write!(f, "(tuple {})", Spaced(patterns)),
+        }
+    }
+}
+
+impl Display for Receiver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ReceiverKind::Tuple(elements) => {
+                write!(f, "(tuple")?;
+                for element in elements {
+                    match element {
+                        Some(name) => write!(f, " {}", name)?,
+                        None => write!(f, " _")?,
+                    }
+                }
+                write!(f, ")")
+            },
+            ReceiverKind::Fields(fields) => write!(f, "(fields {})", Spaced(fields)),
+            ReceiverKind::SelfValue { alias: None } => write!(f, "self"),
+            ReceiverKind::SelfValue { alias: Some(alias) } => write!(f, "(self as {})", alias),
+        }
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "(block")?;
+        for statement in &self.statements {
+            write!(f, " {}", statement)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Val { property, .. } => {
+                write!(f, "(val {}", property.name)?;
+                write_property(f, property)?;
+                write!(f, ")")
+            },
+            Statement::Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Literal(literal) => write!(f, "{}", literal),
+            ExprKind::String(parts) => {
+                write!(f, "(string")?;
+                for part in parts {
+                    match part {
+                        StringPart::Text(text) => write!(f, " {:?}", text)?,
+                        StringPart::Interpolation(expr) => write!(f, " {}", expr)?,
+                    }
+                }
+                write!(f, ")")
+            },
+            ExprKind::Path(path) => write!(f, "{}", path),
+            ExprKind::SelfValue => write!(f, "self"),
+            ExprKind::Tuple(exprs) => write!(f, "(tuple {})", Spaced(exprs)),
+            ExprKind::Unary { op, operand } => write!(f, "({} {})", op.symbol(), operand),
+            ExprKind::Binary { op, lhs, rhs } => write!(f, "({} {} {})", op.symbol(), lhs, rhs),
+            ExprKind::Postfix { op, operand } => write!(f, "(postfix {} {})", op.symbol(), operand),
+            ExprKind::Assign { op, target, value } => {
+                let op = op.map(BinaryOp::symbol).unwrap_or("");
+                write!(f, "({}= {} {})", op, target, value)
+            },
+            ExprKind::Call { callee, args } => {
+                write!(f, "(call {}", callee)?;
+                for arg in args {
+                    match &arg.name {
+                        Some(name) => write!(f, " (named {} {})", name, arg.value)?,
+                        None => write!(f, " {}", arg.value)?,
+                    }
+                }
+                write!(f, ")")
+            },
+            ExprKind::Index { object, index } => write!(f, "(index {} {})", object, index),
+            ExprKind::Member { object, name } => write!(f, "(. {} {})", object, name),
+            ExprKind::Companion { parent, name } => write!(f, "(# {} {})", parent, name),
+            ExprKind::Construct { ty, fields } => {
+                write!(f, "(construct {}", ty)?;
+                for field in fields {
+                    match &field.value {
+                        Some(value) => write!(f, " ({} {})", field.name, value)?,
+                        None => write!(f, " {}", field.name)?,
+                    }
+                }
+                write!(f, ")")
+            },
+            ExprKind::Lambda { params, body } => write!(f, "(lambda ({}) {})", Spaced(params), body),
+            ExprKind::If { condition, then, otherwise } => {
+                write!(f, "(if {} {}", condition, then)?;
+                if let Some(otherwise) = otherwise {
+                    write!(f, " {}", otherwise)?;
+                }
+                write!(f, ")")
+            },
+            ExprKind::When { subject, arms } => {
+                write!(f, "(when {}", subject)?;
+                for arm in arms {
+                    write!(f, " (-> {} {})", arm.pattern, arm.body)?;
+                }
+                write!(f, ")")
+            },
+            ExprKind::Block(block) => write!(f, "{}", block),
+            ExprKind::Throw(value) => write!(f, "(throw {})", value),
+        }
+    }
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Integer { value, suffix } => {
+                write!(f, "{}", value)?;
+                write_suffix(f, *suffix)
+            },
+            Literal::Decimal { value, suffix } => {
+                write!(f, "{:?}", value)?;
+                write_suffix(f, *suffix)
+            },
+            Literal::String(text) => write!(f, "{:?}", text),
+            Literal::Character(ch) => write!(f, "{:?}", ch),
+            Literal::Byte(byte) => write!(f, "(byte {})", byte),
+            Literal::ByteString(bytes) => write!(f, "(bytes {})", Spaced(bytes)),
+            Literal::Bool(value) => write!(f, "{}", value),
+            Literal::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+fn write_suffix(f: &mut Formatter<'_>, suffix: Option<maple_lexer::NumberSuffix>) -> fmt::Result {
+    match suffix {
+        Some(suffix) => write!(f, "{}", format!("{:?}", suffix).to_lowercase()),
+        None => Ok(()),
+    }
+}
+
+/// Display a list of things with spaces between them.
+struct Spaced<'a, T>(&'a [T]);
+
+impl<'a, T: Display> Display for Spaced<'a, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Separated(self.0, " "))
+    }
+}
+
+struct Separated<'a, T>(&'a [T], &'static str);
+
+impl<'a, T: Display> Display for Separated<'a, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(self.1)?;
+            }
+            write!(f, "{}", item)?;
+        }
+
+        Ok(())
+    }
+}
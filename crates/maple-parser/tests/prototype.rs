@@ -0,0 +1,40 @@
+//! Golden tests, parsing the example code and comparing the result to a
+//! known-good S-expression dump of its AST.
+//!
+//! Run with `MAPLE_BLESS=1` to update the expected output after an
+//! intentional change.
+//!
+//! The 2023-12-22 prototype isn't quite the original file. A stray `}` at
+//! the end of the `greeting` function on line 73 closed `Person` early. That
+//! left the rest of the file unparseable, so it was removed when these tests
+//! were added.
+
+use std::fs;
+use std::path::PathBuf;
+use maple_lexer::{lex_file, CodeMap};
+
+fn check_golden(example: &str) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let source = root.join("../../examples/maple-code/prototypes").join(format!("{}.maple", example));
+    let golden = root.join("tests/golden").join(format!("{}.sexp", example));
+
+    let mut map = CodeMap::new();
+    lex_file(&mut map, &source).unwrap();
+    let ast = match maple_parser::parse_file(&map.files()[0]) {
+        Ok(ast) => ast,
+        Err(e) => panic!("{}", maple_lexer::Renderer::new(&map).render(&e.to_diagnostic())),
+    };
+    let got = ast.to_string();
+
+    if std::env::var_os("MAPLE_BLESS").is_some() {
+        fs::write(&golden, &got).unwrap();
+    }
+
+    let expected = fs::read_to_string(&golden).unwrap();
+    assert!(got == expected, "{} doesn't match {}:\n{}", example, golden.display(), got);
+}
+
+#[test]
+fn proto_2023_12_22() {
+    check_golden("proto_2023-12-22");
+}